@@ -0,0 +1,284 @@
+use std::io::Write;
+
+use crate::{distance_metric::{self, DistanceMetric}, geo_error::GeoError, geo_point::Point};
+
+/*
+Binary layout of a saved KDTree. All values are little-endian, records have a fixed size
+and refer to each other by indices only => the file can be read in place without pointers.
+
+header      | magic (8) | version u32 | metric u32 | radius f64 | n_stop u64 | root u64 | node_count u64 | point_count u64 |
+            |   metric = DistanceMetric::CODE, radius = DistanceMetric::radius
+nodes       | node_count records: kind u32 | dimension u32 | splitter f64 | first u64 | second u64 | count u64 |
+            |   Node => first = left child, second = right child
+            |   Leaf => first = index of the first point, second = number of points
+            |   count = number of points in the subtree of the node
+points      | point_count records: id u64 | lat f64 | lon f64 |
+checksum    | CRC-32 of everything above, u32
+*/
+pub const MAGIC: &[u8; 8] = b"GSKDTREE";
+pub const VERSION: u32 = 2;
+
+pub const HEADER_SIZE: usize = 56;
+pub const NODE_RECORD_SIZE: usize = 40;
+pub const POINT_RECORD_SIZE: usize = 24;
+pub const CHECKSUM_SIZE: usize = 4;
+
+pub const KIND_NODE: u32 = 0;
+pub const KIND_LEAF: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header {
+    pub metric: u32,
+    pub radius: f64,
+    pub n_stop: usize,
+    pub root: usize,
+    pub node_count: usize,
+    pub point_count: usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeRecord {
+    pub kind: u32,
+    pub dimension: u32,
+    pub splitter: f64,
+    pub first: usize,
+    pub second: usize,
+    pub count: usize
+}
+
+impl Header {
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), GeoError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&self.metric.to_le_bytes())?;
+        writer.write_all(&self.radius.to_le_bytes())?;
+        writer.write_all(&(self.n_stop as u64).to_le_bytes())?;
+        writer.write_all(&(self.root as u64).to_le_bytes())?;
+        writer.write_all(&(self.node_count as u64).to_le_bytes())?;
+        writer.write_all(&(self.point_count as u64).to_le_bytes())?;
+        return Ok(());
+    }
+
+    // read and check the header. Checks the whole file size, so records can be read without bound checks
+    pub fn read_from(bytes: &[u8]) -> Result<Self, GeoError> {
+
+        if bytes.len() < HEADER_SIZE + CHECKSUM_SIZE || &bytes[..8] != MAGIC {
+            return Err(GeoError::InvalidFormat("file is not a saved KDTree".to_string()));
+        }
+
+        let version = read_u32(bytes, 8);
+        if version != VERSION {
+            return Err(GeoError::UnsupportedVersion { found: version, expected: VERSION });
+        }
+
+        let header = Header {
+            metric: read_u32(bytes, 12),
+            radius: read_f64(bytes, 16),
+            n_stop: read_usize(bytes, 24)?,
+            root: read_usize(bytes, 32)?,
+            node_count: read_usize(bytes, 40)?,
+            point_count: read_usize(bytes, 48)?
+        };
+
+        let expected_size = header.node_count.checked_mul(NODE_RECORD_SIZE)
+            .and_then(|size| size.checked_add(header.point_count.checked_mul(POINT_RECORD_SIZE)?))
+            .and_then(|size| size.checked_add(HEADER_SIZE + CHECKSUM_SIZE));
+        if expected_size != Some(bytes.len()) {
+            return Err(GeoError::InvalidFormat(format!("file size {} does not match {} nodes and {} points", bytes.len(), header.node_count, header.point_count)));
+        }
+
+        if header.root >= header.node_count {
+            return Err(GeoError::InvalidFormat(format!("root {} is out of {} nodes", header.root, header.node_count)));
+        }
+
+        return Ok(header);
+    }
+
+    pub fn node_offset(&self, idx: usize) -> usize {
+        return HEADER_SIZE + idx * NODE_RECORD_SIZE;
+    }
+
+    pub fn point_offset(&self, idx: usize) -> usize {
+        return HEADER_SIZE + self.node_count * NODE_RECORD_SIZE + idx * POINT_RECORD_SIZE;
+    }
+}
+
+impl NodeRecord {
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), GeoError> {
+        writer.write_all(&self.kind.to_le_bytes())?;
+        writer.write_all(&self.dimension.to_le_bytes())?;
+        writer.write_all(&self.splitter.to_le_bytes())?;
+        writer.write_all(&(self.first as u64).to_le_bytes())?;
+        writer.write_all(&(self.second as u64).to_le_bytes())?;
+        writer.write_all(&(self.count as u64).to_le_bytes())?;
+        return Ok(());
+    }
+
+    pub fn read_from(bytes: &[u8], offset: usize) -> Self {
+        return NodeRecord {
+            kind: read_u32(bytes, offset),
+            dimension: read_u32(bytes, offset + 4),
+            splitter: read_f64(bytes, offset + 8),
+            first: read_u64(bytes, offset + 16) as usize,
+            second: read_u64(bytes, offset + 24) as usize,
+            count: read_u64(bytes, offset + 32) as usize
+        };
+    }
+}
+
+pub fn write_point<W: Write>(writer: &mut W, point: &Point) -> Result<(), GeoError> {
+    writer.write_all(&(point.id as u64).to_le_bytes())?;
+    writer.write_all(&point.lat.to_le_bytes())?;
+    writer.write_all(&point.lon.to_le_bytes())?;
+    return Ok(());
+}
+
+pub fn read_point(bytes: &[u8], offset: usize) -> Point {
+    return Point {
+        id: read_u64(bytes, offset) as usize,
+        lat: read_f64(bytes, offset + 8),
+        lon: read_f64(bytes, offset + 16)
+    };
+}
+
+// compare the stored checksum with the checksum of the content
+pub fn verify_checksum(bytes: &[u8]) -> Result<(), GeoError> {
+
+    let content_size = bytes.len() - CHECKSUM_SIZE;
+    let expected = read_u32(bytes, content_size);
+    let found = crc32fast::hash(&bytes[..content_size]);
+
+    if found != expected {
+        return Err(GeoError::ChecksumMismatch { found, expected });
+    }
+
+    return Ok(());
+}
+
+// check that the tree was built for the metric
+pub fn verify_metric<M: DistanceMetric>(header: &Header, metric: &M) -> Result<(), GeoError> {
+    if header.metric != M::CODE || header.radius != metric.radius() {
+        return Err(GeoError::MetricMismatch {
+            found: distance_metric::describe_metric(header.metric, header.radius),
+            expected: distance_metric::describe_metric(M::CODE, metric.radius())
+        });
+    }
+    return Ok(());
+}
+
+// check that node records form a tree: every index is in range, every node has at most one parent and counts are sums of the children
+// reads only the node section, points are checked by verify_points
+pub fn verify_structure(bytes: &[u8], header: &Header) -> Result<(), GeoError> {
+
+    if header.n_stop == 0 {
+        return Err(GeoError::InvalidLeafSize(header.n_stop));
+    }
+
+    let mut referenced = vec![false; header.node_count];
+    referenced[header.root] = true;
+
+    for idx in 0..header.node_count {
+        let record = NodeRecord::read_from(bytes, header.node_offset(idx));
+
+        match record.kind {
+            KIND_NODE => {
+                if record.dimension > 1 {
+                    return Err(GeoError::InvalidFormat(format!("node {} has unknown dimension {}", idx, record.dimension)));
+                }
+                for child in [record.first, record.second] {
+                    if child >= header.node_count || referenced[child] {
+                        return Err(GeoError::InvalidFormat(format!("node {} has invalid child {}", idx, child)));
+                    }
+                    referenced[child] = true;
+                }
+                let children_count = NodeRecord::read_from(bytes, header.node_offset(record.first)).count
+                    .checked_add(NodeRecord::read_from(bytes, header.node_offset(record.second)).count);
+                if children_count != Some(record.count) {
+                    return Err(GeoError::InvalidFormat(format!("node {} has count {} that differs from its children", idx, record.count)));
+                }
+            },
+            KIND_LEAF => {
+                if record.first.checked_add(record.second).is_none_or(|end| end > header.point_count) {
+                    return Err(GeoError::InvalidFormat(format!("points of leaf {} are out of {} points", idx, header.point_count)));
+                }
+                if record.count != record.second {
+                    return Err(GeoError::InvalidFormat(format!("leaf {} has count {} instead of {}", idx, record.count, record.second)));
+                }
+            },
+            kind => {
+                return Err(GeoError::InvalidFormat(format!("node {} has unknown kind {}", idx, kind)));
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+// check that every point has valid coordinates. Reads the whole point section
+pub fn verify_points(bytes: &[u8], header: &Header) -> Result<(), GeoError> {
+    for idx in 0..header.point_count {
+        read_point(bytes, header.point_offset(idx)).validate()?;
+    }
+    return Ok(());
+}
+
+/*
+Writer that calculates the checksum of everything written through it
+*/
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher
+}
+
+impl<W: Write> ChecksumWriter<W> {
+
+    pub fn new(inner: W) -> Self {
+        return ChecksumWriter {
+            inner,
+            hasher: crc32fast::Hasher::new()
+        };
+    }
+
+    // write the checksum at the end and give back the inner writer
+    pub fn finish(mut self) -> Result<W, GeoError> {
+        let checksum = self.hasher.finalize();
+        self.inner.write_all(&checksum.to_le_bytes())?;
+        self.inner.flush()?;
+        return Ok(self.inner);
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        return Ok(written);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return self.inner.flush();
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buffer = [0u8; 4];
+    buffer.copy_from_slice(&bytes[offset..offset + 4]);
+    return u32::from_le_bytes(buffer);
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buffer = [0u8; 8];
+    buffer.copy_from_slice(&bytes[offset..offset + 8]);
+    return u64::from_le_bytes(buffer);
+}
+
+fn read_f64(bytes: &[u8], offset: usize) -> f64 {
+    return f64::from_bits(read_u64(bytes, offset));
+}
+
+fn read_usize(bytes: &[u8], offset: usize) -> Result<usize, GeoError> {
+    let value = read_u64(bytes, offset);
+    return usize::try_from(value).map_err(|_| GeoError::InvalidFormat(format!("value {} does not fit into usize", value)));
+}
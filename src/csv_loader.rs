@@ -0,0 +1,290 @@
+use std::f64::consts::PI;
+use std::fmt::{self, Debug};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use csv::{ByteRecord, ReaderBuilder, Trim};
+
+use crate::{distance_metric::DistanceMetric, geo_error::GeoError, geo_point::Point, kd_tree::KDTree};
+
+// units of coordinates in a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AngleUnit {
+    Degrees,
+    Radians
+}
+
+// what to do with a row that can not be turned into a point (missing field, not a number, coordinate out of range, ...)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MalformedRowPolicy {
+    Skip, // drop the row silently
+    Fail, // stop loading with an error
+    Log // drop the row and report it (see CsvLoader::report_to)
+}
+
+// receiver of malformed rows dropped by MalformedRowPolicy::Log
+pub type RowReporter = Arc<dyn Fn(&GeoError) + Send + Sync>;
+
+/*
+Loader of points from CSV files with a header, e.g. "id,lat,lon,...". Other columns are ignored.
+Rows are read one by one into a reused record => only the points are kept in memory, not the text of the file
+*/
+#[derive(Clone)]
+pub struct CsvLoader {
+    id_column: String,
+    lat_column: String,
+    lon_column: String,
+    units: AngleUnit,
+    policy: MalformedRowPolicy,
+    delimiter: u8,
+    reporter: RowReporter
+}
+
+// the reporter is a closure => it is left out
+impl Debug for CsvLoader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_struct("CsvLoader")
+            .field("id_column", &self.id_column)
+            .field("lat_column", &self.lat_column)
+            .field("lon_column", &self.lon_column)
+            .field("units", &self.units)
+            .field("policy", &self.policy)
+            .field("delimiter", &self.delimiter)
+            .finish_non_exhaustive();
+    }
+}
+
+impl Default for CsvLoader {
+    fn default() -> Self {
+        return CsvLoader::new();
+    }
+}
+
+impl CsvLoader {
+
+    // columns "id", "lat", "lon" in degrees separated by commas, malformed rows are errors. Logged rows go to the log crate as warnings
+    pub fn new() -> Self {
+        return CsvLoader {
+            id_column: "id".to_string(),
+            lat_column: "lat".to_string(),
+            lon_column: "lon".to_string(),
+            units: AngleUnit::Degrees,
+            policy: MalformedRowPolicy::Fail,
+            delimiter: b',',
+            reporter: Arc::new(|error: &GeoError| log::warn!("{}", error))
+        };
+    }
+
+    pub fn id_column(mut self, name: &str) -> Self {
+        self.id_column = name.to_string();
+        return self;
+    }
+
+    pub fn lat_column(mut self, name: &str) -> Self {
+        self.lat_column = name.to_string();
+        return self;
+    }
+
+    pub fn lon_column(mut self, name: &str) -> Self {
+        self.lon_column = name.to_string();
+        return self;
+    }
+
+    pub fn units(mut self, units: AngleUnit) -> Self {
+        self.units = units;
+        return self;
+    }
+
+    pub fn malformed_rows(mut self, policy: MalformedRowPolicy) -> Self {
+        self.policy = policy;
+        return self;
+    }
+
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        return self;
+    }
+
+    // report malformed rows to the callback instead of the log crate (only with MalformedRowPolicy::Log)
+    pub fn report_to<F: Fn(&GeoError) + Send + Sync + 'static>(mut self, reporter: F) -> Self {
+        self.reporter = Arc::new(reporter);
+        return self;
+    }
+
+    // points of the file
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<Vec::<Point>, GeoError> {
+        return self.read(File::open(path)?);
+    }
+
+    // points of the file indexed right away
+    pub fn load_tree<P: AsRef<Path>, M: DistanceMetric>(&self, path: P, n_stop: usize, metric: M) -> Result<KDTree<Point, M>, GeoError> {
+        return KDTree::new(&self.load(path)?, n_stop, metric);
+    }
+
+    // points of CSV data from any reader
+    pub fn read<R: Read>(&self, reader: R) -> Result<Vec::<Point>, GeoError> {
+
+        // rows with missing fields are handled by the policy, not by the reader
+        let mut reader = ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(reader);
+
+        let headers = reader.byte_headers().map_err(Self::read_error)?.clone();
+        let column = |name: &str| {
+            return headers.iter().position(|header| header == name.as_bytes()).ok_or_else(|| GeoError::MissingColumn(name.to_string()));
+        };
+        let columns = (column(&self.id_column)?, column(&self.lat_column)?, column(&self.lon_column)?);
+
+        let mut points = Vec::new();
+        let mut record = ByteRecord::new();
+        while reader.read_byte_record(&mut record).map_err(Self::read_error)? {
+            let line = record.position().map_or(0, |position| position.line());
+            match self.parse_row(&record, columns) {
+                Ok(point) => points.push(point),
+                Err(message) => match self.policy {
+                    MalformedRowPolicy::Skip => {},
+                    MalformedRowPolicy::Fail => return Err(GeoError::MalformedRow { line, message }),
+                    MalformedRowPolicy::Log => (self.reporter)(&GeoError::MalformedRow { line, message })
+                }
+            }
+        }
+
+        return Ok(points);
+    }
+
+    // point of the row or the reason why it is malformed
+    fn parse_row(&self, record: &ByteRecord, (id_idx, lat_idx, lon_idx): (usize, usize, usize)) -> Result<Point, String> {
+
+        let field = |idx: usize, name: &str| {
+            let value = record.get(idx).ok_or_else(|| format!("{} is missing", name))?;
+            return std::str::from_utf8(value).map_err(|_| format!("{} is not valid UTF-8", name));
+        };
+        let number = |idx: usize, name: &str| {
+            let value = field(idx, name)?;
+            return value.parse::<f64>().map_err(|_| format!("{} {:?} is not a number", name, value));
+        };
+
+        let id_value = field(id_idx, &self.id_column)?;
+        let id = id_value.parse::<usize>().map_err(|_| format!("{} {:?} is not a non-negative integer", self.id_column, id_value))?;
+        let (lat, lon) = (number(lat_idx, &self.lat_column)?, number(lon_idx, &self.lon_column)?);
+
+        // coordinates out of range are malformed, the builder would wrap longitudes around the sphere
+        let (lat_limit, lon_limit) = match self.units {
+            AngleUnit::Degrees => (90.0, 180.0),
+            AngleUnit::Radians => (PI / 2.0, PI)
+        };
+        if !(-lat_limit..=lat_limit).contains(&lat) {
+            return Err(format!("{} {} is out of range [{}, {}]", self.lat_column, lat, -lat_limit, lat_limit));
+        }
+        if !(-lon_limit..=lon_limit).contains(&lon) {
+            return Err(format!("{} {} is out of range [{}, {}]", self.lon_column, lon, -lon_limit, lon_limit));
+        }
+
+        let builder = match self.units {
+            AngleUnit::Degrees => Point::builder(id).lat_degrees(lat).lon_degrees(lon),
+            AngleUnit::Radians => Point::builder(id).lat(lat).lon(lon)
+        };
+        return builder.build().map_err(|error| error.to_string());
+    }
+
+    fn read_error(error: csv::Error) -> GeoError {
+        if error.is_io_error() {
+            return GeoError::Io(error.to_string());
+        }
+        return GeoError::InvalidFormat(error.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use std::sync::{Arc, Mutex};
+
+    use crate::geo_error::GeoError;
+    use super::{AngleUnit, CsvLoader, MalformedRowPolicy};
+
+    // header, a good row and rows with all kinds of problems
+    const ROWS: &str = "id,name,lat,lon\n\
+                        1,Munich,48.1371,11.5754\n\
+                        2,far east,10,200\n\
+                        3,north,91,0\n\
+                        4,short,10\n\
+                        5,text,abc,1\n\
+                        x,no id,1,1\n\
+                        6,Fiji,-17.7134,-180\n";
+
+    #[test]
+    fn policies_for_malformed_rows() {
+        let skipped = CsvLoader::new().malformed_rows(MalformedRowPolicy::Skip).read(ROWS.as_bytes()).unwrap();
+        assert_eq!(skipped.iter().map(|point| point.id).collect::<Vec<usize>>(), vec![1, 6]);
+        assert!(f64::abs(skipped[0].lat_degrees() - 48.1371) < 1e-12 && f64::abs(skipped[0].lon_degrees() - 11.5754) < 1e-12);
+        assert!(f64::abs(skipped[1].lon_degrees() + 180.0) < 1e-12);
+
+        // malformed rows are reported in the order of lines and dropped
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reported);
+        let logged = CsvLoader::new().malformed_rows(MalformedRowPolicy::Log).report_to(move |error| sink.lock().unwrap().push(error.clone())).read(ROWS.as_bytes()).unwrap();
+        assert_eq!(format!("{:?}", logged), format!("{:?}", skipped));
+
+        let messages = ["lon 200 is out of range [-180, 180]", "lat 91 is out of range [-90, 90]", "lon is missing", "lat \"abc\" is not a number", "id \"x\" is not a non-negative integer"];
+        let expected: Vec<GeoError> = messages.iter().enumerate().map(|(idx, message)| GeoError::MalformedRow { line: idx as u64 + 3, message: message.to_string() }).collect();
+        assert_eq!(*reported.lock().unwrap(), expected);
+
+        // other policies do not report
+        let sink = Arc::clone(&reported);
+        CsvLoader::new().malformed_rows(MalformedRowPolicy::Skip).report_to(move |error| sink.lock().unwrap().push(error.clone())).read(ROWS.as_bytes()).unwrap();
+        assert_eq!(reported.lock().unwrap().len(), messages.len());
+
+        // the first malformed row stops loading. Lines are counted from the header
+        assert_eq!(
+            CsvLoader::new().read(ROWS.as_bytes()).map(|_| ()),
+            Err(GeoError::MalformedRow { line: 3, message: "lon 200 is out of range [-180, 180]".to_string() })
+        );
+
+        // every kind of problem is found when the rows before it are good
+        let lines: Vec<&str> = ROWS.lines().collect();
+        let messages = ["lat 91 is out of range [-90, 90]", "lon is missing", "lat \"abc\" is not a number", "id \"x\" is not a non-negative integer"];
+        for (row, message) in lines[3..7].iter().zip(messages) {
+            let text = format!("{}\n{}\n", lines[0], row);
+            assert_eq!(
+                CsvLoader::new().malformed_rows(MalformedRowPolicy::Fail).read(text.as_bytes()).map(|_| ()),
+                Err(GeoError::MalformedRow { line: 2, message: message.to_string() })
+            );
+        }
+    }
+
+    #[test]
+    fn missing_columns_are_errors_for_every_policy() {
+        for policy in [MalformedRowPolicy::Skip, MalformedRowPolicy::Fail, MalformedRowPolicy::Log] {
+            let loader = CsvLoader::new().malformed_rows(policy);
+            assert_eq!(loader.read("id,latitude,lon\n1,2,3\n".as_bytes()).map(|_| ()), Err(GeoError::MissingColumn("lat".to_string())));
+
+            // columns with other names and another delimiter
+            let loader = loader.id_column("key").lat_column("latitude").lon_column("longitude").delimiter(b';');
+            let points = loader.read("longitude; key; latitude\n11.5754; 3; 48.1371\n".as_bytes()).unwrap();
+            assert_eq!(points.len(), 1);
+            assert_eq!(points[0].id, 3);
+            assert!(f64::abs(points[0].lat_degrees() - 48.1371) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn radians_are_taken_as_they_are() {
+        let text = format!("id,lat,lon\n1,0.5,-1\n2,{},{}\n3,0.1,7\n4,2,0.1\n", -PI / 2.0, PI);
+        let loader = CsvLoader::new().units(AngleUnit::Radians).malformed_rows(MalformedRowPolicy::Skip);
+
+        let points = loader.read(text.as_bytes()).unwrap();
+        assert_eq!(points.iter().map(|point| (point.id, point.lat, point.lon)).collect::<Vec<(usize, f64, f64)>>(), vec![(1, 0.5, -1.0), (2, -PI / 2.0, PI)]);
+
+        // longitudes out of range are not wrapped around
+        let loader = loader.malformed_rows(MalformedRowPolicy::Fail);
+        let error = loader.read(text.replace("4,2,0.1\n", "").as_bytes()).map(|_| ());
+        assert_eq!(error, Err(GeoError::MalformedRow { line: 4, message: format!("lon 7 is out of range [{}, {}]", -PI, PI) }));
+        let error = loader.read(text.replace("3,0.1,7\n", "").as_bytes()).map(|_| ());
+        assert_eq!(error, Err(GeoError::MalformedRow { line: 4, message: format!("lat 2 is out of range [{}, {}]", -PI / 2.0, PI / 2.0) }));
+    }
+}
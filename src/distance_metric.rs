@@ -0,0 +1,402 @@
+use std::f64::consts::PI;
+use std::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ellipsoid_helper::EllipsoidHelper, geo_error::GeoError, geo_point::Point, search_box::SearchBox, sphere_helper::SphereHelper};
+
+/*
+Way to measure distances between points. An index is built for one metric and uses it for all distance queries.
+Besides the distance itself, every metric bounds distances for boxes of tree nodes => subtrees can be skipped
+without changing results. Bounds must be conservative: never larger (lower bounds) or smaller (upper bounds) than real distances
+*/
+pub trait DistanceMetric: Debug + Clone + Copy + PartialEq + Send + Sync {
+
+    // identifier of the metric in saved trees (see binary_format)
+    const CODE: u32;
+
+    fn validate(&self) -> Result<(), GeoError> {
+        return Ok(());
+    }
+
+    // radius of the globe, unit of length for planar data. Rounding tolerances are relative to it, saved trees store it.
+    // All metrics take points in the ranges of Point (see Point::validate), also planar ones (see Euclidean)
+    fn radius(&self) -> f64;
+
+    fn distance(&self, p1: &Point, p2: &Point) -> f64;
+
+    // SearchBoxes that contain all points within the distance. The second one is used for the other side of the antimeridian
+    fn find_box(&self, point: &Point, distance: f64) -> Result<(Option::<SearchBox>, Option::<SearchBox>), GeoError>;
+
+    // SearchBoxes that contain all points within the distance from any point of the box
+    fn find_box_around_box(&self, search_box: &SearchBox, distance: f64) -> (Option::<SearchBox>, Option::<SearchBox>);
+
+    // lower bound of the distance from the point to any point of the box
+    fn distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64;
+
+    // upper bound of the distance from the point to any point of the box
+    fn max_distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64;
+
+    // upper bound of the distance between any points of two boxes
+    fn max_distance_between_boxes(&self, first: &SearchBox, second: &SearchBox) -> f64;
+}
+
+// readable name of a metric saved with the given code and radius
+pub fn describe_metric(code: u32, radius: f64) -> String {
+    match code {
+        Haversine::CODE => format!("haversine on sphere of radius {}", radius),
+        Vincenty::CODE => "vincenty on WGS84 ellipsoid".to_string(),
+        LawOfCosines::CODE => format!("law of cosines on sphere of radius {}", radius),
+        Equirectangular::CODE => format!("equirectangular approximation on sphere of radius {}", radius),
+        Euclidean::CODE => "euclidean on plane".to_string(),
+        _ => format!("unknown metric {}", code)
+    }
+}
+
+// great-circle distance by the haversine formula (see SphereHelper)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Haversine {
+    pub radius: f64
+}
+
+impl Haversine {
+    pub fn new(radius: f64) -> Self {
+        return Haversine { radius };
+    }
+}
+
+impl DistanceMetric for Haversine {
+    const CODE: u32 = 0;
+
+    fn validate(&self) -> Result<(), GeoError> {
+        return SphereHelper::validate_radius(self.radius);
+    }
+
+    fn radius(&self) -> f64 {
+        return self.radius;
+    }
+
+    fn distance(&self, p1: &Point, p2: &Point) -> f64 {
+        return SphereHelper::distance(p1, p2, self.radius);
+    }
+
+    fn find_box(&self, point: &Point, distance: f64) -> Result<(Option::<SearchBox>, Option::<SearchBox>), GeoError> {
+        return SphereHelper::find_box(point, distance, self.radius);
+    }
+
+    fn find_box_around_box(&self, search_box: &SearchBox, distance: f64) -> (Option::<SearchBox>, Option::<SearchBox>) {
+        return SphereHelper::find_box_around_box(search_box, distance, self.radius);
+    }
+
+    fn distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64 {
+        return SphereHelper::distance_to_box(point, search_box, self.radius);
+    }
+
+    fn max_distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64 {
+        return SphereHelper::max_distance_to_box(point, search_box, self.radius);
+    }
+
+    fn max_distance_between_boxes(&self, first: &SearchBox, second: &SearchBox) -> f64 {
+        return SphereHelper::max_distance_between_boxes(first, second, self.radius);
+    }
+}
+
+// great-circle distance by the spherical law of cosines https://en.wikipedia.org/wiki/Great-circle_distance#Formulae
+// the same circle as haversine => the same bounds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LawOfCosines {
+    pub radius: f64
+}
+
+impl LawOfCosines {
+    pub fn new(radius: f64) -> Self {
+        return LawOfCosines { radius };
+    }
+}
+
+impl DistanceMetric for LawOfCosines {
+    const CODE: u32 = 2;
+
+    fn validate(&self) -> Result<(), GeoError> {
+        return SphereHelper::validate_radius(self.radius);
+    }
+
+    fn radius(&self) -> f64 {
+        return self.radius;
+    }
+
+    fn distance(&self, p1: &Point, p2: &Point) -> f64 {
+        let cos_angle = f64::sin(p1.lat) * f64::sin(p2.lat) + f64::cos(p1.lat) * f64::cos(p2.lat) * f64::cos(p1.lon - p2.lon);
+        return f64::acos(f64::clamp(cos_angle, -1.0, 1.0)) * self.radius;
+    }
+
+    fn find_box(&self, point: &Point, distance: f64) -> Result<(Option::<SearchBox>, Option::<SearchBox>), GeoError> {
+        return SphereHelper::find_box(point, distance, self.radius);
+    }
+
+    fn find_box_around_box(&self, search_box: &SearchBox, distance: f64) -> (Option::<SearchBox>, Option::<SearchBox>) {
+        return SphereHelper::find_box_around_box(search_box, distance, self.radius);
+    }
+
+    fn distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64 {
+        return SphereHelper::distance_to_box(point, search_box, self.radius);
+    }
+
+    fn max_distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64 {
+        return SphereHelper::max_distance_to_box(point, search_box, self.radius);
+    }
+
+    fn max_distance_between_boxes(&self, first: &SearchBox, second: &SearchBox) -> f64 {
+        return SphereHelper::max_distance_between_boxes(first, second, self.radius);
+    }
+}
+
+// geodesic distance on the WGS84 ellipsoid in km (see EllipsoidHelper)
+// the geodesic lies between great-circle arcs on the spheres of the smallest and the largest radius of curvature => bounds come from them
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Vincenty;
+
+impl DistanceMetric for Vincenty {
+    const CODE: u32 = 1;
+
+    fn radius(&self) -> f64 {
+        return EllipsoidHelper::SEMI_MAJOR_AXIS;
+    }
+
+    fn distance(&self, p1: &Point, p2: &Point) -> f64 {
+        return EllipsoidHelper::distance(p1, p2);
+    }
+
+    fn find_box(&self, point: &Point, distance: f64) -> Result<(Option::<SearchBox>, Option::<SearchBox>), GeoError> {
+        return SphereHelper::find_box(point, distance, EllipsoidHelper::min_radius());
+    }
+
+    fn find_box_around_box(&self, search_box: &SearchBox, distance: f64) -> (Option::<SearchBox>, Option::<SearchBox>) {
+        return SphereHelper::find_box_around_box(search_box, distance, EllipsoidHelper::min_radius());
+    }
+
+    fn distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64 {
+        return SphereHelper::distance_to_box(point, search_box, EllipsoidHelper::min_radius());
+    }
+
+    fn max_distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64 {
+        return SphereHelper::max_distance_to_box(point, search_box, EllipsoidHelper::max_radius());
+    }
+
+    fn max_distance_between_boxes(&self, first: &SearchBox, second: &SearchBox) -> f64 {
+        return SphereHelper::max_distance_between_boxes(first, second, EllipsoidHelper::max_radius());
+    }
+}
+
+/*
+Equirectangular approximation https://en.wikipedia.org/wiki/Equirectangular_projection
+d = R * sqrt(d_lat^2 + (d_lon * cos(mean_lat))^2). Fast and good for short distances
+Bounds take the smallest gaps in latitude and longitude together with the extreme values of cos(mean_lat)
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Equirectangular {
+    pub radius: f64
+}
+
+impl Equirectangular {
+    pub fn new(radius: f64) -> Self {
+        return Equirectangular { radius };
+    }
+
+    // smallest and largest cosine of the mean latitude of two points with latitudes in the ranges
+    fn cos_mean_lat(first: (f64, f64), second: (f64, f64)) -> (f64, f64) {
+        let (mean_from, mean_to) = ((first.0 + second.0) / 2.0, (first.1 + second.1) / 2.0);
+        let smallest = f64::cos(f64::max(f64::abs(mean_from), f64::abs(mean_to)));
+        let largest = if mean_from <= 0.0 && 0.0 <= mean_to { 1.0 } else { f64::cos(f64::min(f64::abs(mean_from), f64::abs(mean_to))) };
+        return (smallest, largest);
+    }
+}
+
+impl DistanceMetric for Equirectangular {
+    const CODE: u32 = 3;
+
+    fn validate(&self) -> Result<(), GeoError> {
+        return SphereHelper::validate_radius(self.radius);
+    }
+
+    fn radius(&self) -> f64 {
+        return self.radius;
+    }
+
+    fn distance(&self, p1: &Point, p2: &Point) -> f64 {
+        let x = SphereHelper::normalize_lon(p2.lon - p1.lon) * f64::cos((p1.lat + p2.lat) / 2.0);
+        let y = p2.lat - p1.lat;
+        return f64::hypot(x, y) * self.radius;
+    }
+
+    fn find_box(&self, point: &Point, distance: f64) -> Result<(Option::<SearchBox>, Option::<SearchBox>), GeoError> {
+
+        point.validate()?;
+        SphereHelper::validate_distance(distance)?;
+
+        let d_lat = distance / self.radius;
+        let (lat_from, lat_to) = (f64::max(point.lat - d_lat, -PI / 2.0), f64::min(point.lat + d_lat, PI / 2.0));
+
+        // the difference in longitude is the largest where cos(mean_lat) is the smallest
+        let (cos_smallest, _) = Self::cos_mean_lat((point.lat, point.lat), (lat_from, lat_to));
+        if cos_smallest <= 0.0 || d_lat / cos_smallest >= PI {
+            return Ok((Some(SearchBox { lat_from, lat_to, lon_from: -PI, lon_to: PI }), None));
+        }
+        let d_lon = d_lat / cos_smallest;
+
+        // corners go over the antimeridian => construct_searchbox splits the box
+        let corner_west = Point { id: point.id, lat: lat_from, lon: SphereHelper::normalize_lon(point.lon - d_lon) };
+        let corner_east = Point { id: point.id, lat: lat_to, lon: SphereHelper::normalize_lon(point.lon + d_lon) };
+        return SphereHelper::construct_searchbox(&corner_west, &corner_east);
+    }
+
+    fn find_box_around_box(&self, search_box: &SearchBox, distance: f64) -> (Option::<SearchBox>, Option::<SearchBox>) {
+
+        let d_lat = distance / self.radius;
+        let (lat_from, lat_to) = (f64::max(search_box.lat_from - d_lat, -PI / 2.0), f64::min(search_box.lat_to + d_lat, PI / 2.0));
+
+        // the same bound as in find_box with the latitudes of the whole box
+        let (cos_smallest, _) = Self::cos_mean_lat((search_box.lat_from, search_box.lat_to), (lat_from, lat_to));
+        if cos_smallest <= 0.0 || d_lat / cos_smallest >= PI {
+            return (Some(SearchBox { lat_from, lat_to, lon_from: -PI, lon_to: PI }), None);
+        }
+        let d_lon = d_lat / cos_smallest;
+
+        return SphereHelper::wrap_searchbox(lat_from, lat_to, search_box.lon_from - d_lon, search_box.lon_to + d_lon);
+    }
+
+    fn distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64 {
+        let point_box = SearchBox::from_point(point);
+        let (cos_smallest, _) = Self::cos_mean_lat((point.lat, point.lat), (search_box.lat_from, search_box.lat_to));
+        return f64::hypot(point_box.lon_gap(search_box) * cos_smallest, point_box.lat_gap(search_box)) * self.radius;
+    }
+
+    fn max_distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64 {
+
+        let d_lat = f64::max(f64::abs(point.lat - search_box.lat_from), f64::abs(point.lat - search_box.lat_to));
+
+        // the box contains the opposite meridian => longitude differs up to PI
+        let opposite = SphereHelper::normalize_lon(point.lon + PI);
+        let d_lon = if search_box.lon_from <= opposite && opposite <= search_box.lon_to {
+            PI
+        } else {
+            f64::max(
+                f64::abs(SphereHelper::normalize_lon(search_box.lon_from - point.lon)),
+                f64::abs(SphereHelper::normalize_lon(search_box.lon_to - point.lon))
+            )
+        };
+
+        let (_, cos_largest) = Self::cos_mean_lat((point.lat, point.lat), (search_box.lat_from, search_box.lat_to));
+        return f64::hypot(d_lon * cos_largest, d_lat) * self.radius;
+    }
+
+    fn max_distance_between_boxes(&self, first: &SearchBox, second: &SearchBox) -> f64 {
+        let (_, cos_largest) = Self::cos_mean_lat((first.lat_from, first.lat_to), (second.lat_from, second.lat_to));
+        return f64::hypot(first.max_lon_difference(second) * cos_largest, first.max_lat_difference(second)) * self.radius;
+    }
+}
+
+/*
+Euclidean distance for planar data: longitude = x, latitude = y, no wrap around the antimeridian.
+Points are validated like points on the globe => planar data must be scaled into x in [-π, π] and y in [-π/2, π/2]
+before indexing, distances are measured in the scaled units. One factor for both axes keeps distances proportional,
+e.g. s = min(π / max |x|, π/2 / max |y|)
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Euclidean;
+
+impl Euclidean {
+
+    // gaps between ranges on the plane, 0 if they overlap
+    fn gaps(first: &SearchBox, second: &SearchBox) -> (f64, f64) {
+        let d_lat = f64::max(0.0, f64::max(second.lat_from - first.lat_to, first.lat_from - second.lat_to));
+        let d_lon = f64::max(0.0, f64::max(second.lon_from - first.lon_to, first.lon_from - second.lon_to));
+        return (d_lat, d_lon);
+    }
+}
+
+impl DistanceMetric for Euclidean {
+    const CODE: u32 = 4;
+
+    fn radius(&self) -> f64 {
+        return 1.0;
+    }
+
+    fn distance(&self, p1: &Point, p2: &Point) -> f64 {
+        return f64::hypot(p2.lon - p1.lon, p2.lat - p1.lat);
+    }
+
+    fn find_box(&self, point: &Point, distance: f64) -> Result<(Option::<SearchBox>, Option::<SearchBox>), GeoError> {
+
+        point.validate()?;
+        SphereHelper::validate_distance(distance)?;
+
+        return Ok((Some(SearchBox {
+            lat_from: f64::max(point.lat - distance, -PI / 2.0),
+            lat_to: f64::min(point.lat + distance, PI / 2.0),
+            lon_from: f64::max(point.lon - distance, -PI),
+            lon_to: f64::min(point.lon + distance, PI)
+        }), None));
+    }
+
+    fn find_box_around_box(&self, search_box: &SearchBox, distance: f64) -> (Option::<SearchBox>, Option::<SearchBox>) {
+        return (Some(SearchBox {
+            lat_from: f64::max(search_box.lat_from - distance, -PI / 2.0),
+            lat_to: f64::min(search_box.lat_to + distance, PI / 2.0),
+            lon_from: f64::max(search_box.lon_from - distance, -PI),
+            lon_to: f64::min(search_box.lon_to + distance, PI)
+        }), None);
+    }
+
+    fn distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64 {
+        let (d_lat, d_lon) = Self::gaps(&SearchBox::from_point(point), search_box);
+        return f64::hypot(d_lon, d_lat);
+    }
+
+    fn max_distance_to_box(&self, point: &Point, search_box: &SearchBox) -> f64 {
+        let d_lat = f64::max(f64::abs(point.lat - search_box.lat_from), f64::abs(point.lat - search_box.lat_to));
+        let d_lon = f64::max(f64::abs(point.lon - search_box.lon_from), f64::abs(point.lon - search_box.lon_to));
+        return f64::hypot(d_lon, d_lat);
+    }
+
+    // no wrap around the antimeridian => the largest difference is between the opposite ends
+    fn max_distance_between_boxes(&self, first: &SearchBox, second: &SearchBox) -> f64 {
+        let d_lon = f64::max(second.lon_to - first.lon_from, first.lon_to - second.lon_from);
+        return f64::hypot(d_lon, first.max_lat_difference(second));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helper::{assert_tree_matches_brute_force, centers, clustered_points, seeded, RADIUS};
+    use super::{Equirectangular, Euclidean, Haversine, LawOfCosines, Vincenty};
+
+    // distances in km for metrics on the globe
+    const DISTANCES: [f64; 6] = [0.0, 1.0, 50.0, 500.0, 5_000.0, 30_000.0];
+
+    #[test]
+    fn haversine_matches_brute_force() {
+        assert_tree_matches_brute_force(Haversine::new(RADIUS), &clustered_points(&mut seeded(41), 5_000), &centers(), &DISTANCES);
+    }
+
+    #[test]
+    fn law_of_cosines_matches_brute_force() {
+        assert_tree_matches_brute_force(LawOfCosines::new(RADIUS), &clustered_points(&mut seeded(43), 5_000), &centers(), &DISTANCES);
+    }
+
+    #[test]
+    fn vincenty_matches_brute_force() {
+        assert_tree_matches_brute_force(Vincenty, &clustered_points(&mut seeded(47), 5_000), &centers(), &DISTANCES);
+    }
+
+    #[test]
+    fn equirectangular_matches_brute_force() {
+        assert_tree_matches_brute_force(Equirectangular::new(RADIUS), &clustered_points(&mut seeded(53), 5_000), &centers(), &DISTANCES);
+    }
+
+    // coordinates are the plane scaled into the ranges of Point => distances in the same units
+    #[test]
+    fn euclidean_matches_brute_force() {
+        assert_tree_matches_brute_force(Euclidean, &clustered_points(&mut seeded(59), 5_000), &centers(), &[0.0, 0.001, 0.05, 0.5, 2.0, 10.0]);
+    }
+}
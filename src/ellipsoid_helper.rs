@@ -0,0 +1,175 @@
+use crate::geo_point::Point;
+use crate::sphere_helper::SphereHelper;
+
+pub struct EllipsoidHelper {
+    // this struct does not have any field
+    // static methods only
+}
+
+/*
+WGS84 reference ellipsoid https://en.wikipedia.org/wiki/World_Geodetic_System
+Latitude of points is the geodetic latitude. Measure unit = km
+*/
+impl EllipsoidHelper {
+
+    // semi-major axis
+    pub const SEMI_MAJOR_AXIS: f64 = 6378.137;
+
+    // flattening
+    pub const FLATTENING: f64 = 1.0 / 298.257223563;
+
+    // Vincenty's iteration stops when longitude on the auxiliary sphere changes less than this
+    const TOLERANCE: f64 = 1e-12;
+    const MAX_ITERATIONS: usize = 200;
+
+    pub fn semi_minor_axis() -> f64 {
+        return Self::SEMI_MAJOR_AXIS * (1.0 - Self::FLATTENING);
+    }
+
+    // smallest radius of curvature = meridian radius at the equator a * (1 - e^2)
+    // geodesic between two points is not shorter than the great-circle arc between them on the sphere of this radius
+    pub fn min_radius() -> f64 {
+        let e2 = Self::FLATTENING * (2.0 - Self::FLATTENING);
+        return Self::SEMI_MAJOR_AXIS * (1.0 - e2);
+    }
+
+    // largest radius of curvature = radius at the poles a^2 / b
+    // geodesic between two points is not longer than the great-circle arc between them on the sphere of this radius
+    pub fn max_radius() -> f64 {
+        return Self::SEMI_MAJOR_AXIS * Self::SEMI_MAJOR_AXIS / Self::semi_minor_axis();
+    }
+
+    // length of the geodesic between two points https://en.wikipedia.org/wiki/Vincenty%27s_formulae#Inverse_problem
+    // for nearly antipodal points the iteration may not converge => the last value is kept within the bounds of min_radius and max_radius
+    pub fn distance(p1: &Point, p2: &Point) -> f64 {
+
+        let a = Self::SEMI_MAJOR_AXIS;
+        let f = Self::FLATTENING;
+        let b = Self::semi_minor_axis();
+
+        // reduced latitudes
+        let u1 = f64::atan((1.0 - f) * f64::tan(p1.lat));
+        let u2 = f64::atan((1.0 - f) * f64::tan(p2.lat));
+        let (sin_u1, cos_u1) = f64::sin_cos(u1);
+        let (sin_u2, cos_u2) = f64::sin_cos(u2);
+
+        let d_lon = SphereHelper::normalize_lon(p2.lon - p1.lon);
+        let mut lambda = d_lon;
+
+        let mut converged = false;
+        let (mut sin_sigma, mut cos_sigma, mut sigma, mut cos2_alpha, mut cos_2sigma_m) = (0.0, 1.0, 0.0, 1.0, 0.0);
+
+        for _ in 0..Self::MAX_ITERATIONS {
+            let (sin_lambda, cos_lambda) = f64::sin_cos(lambda);
+
+            sin_sigma = f64::hypot(cos_u2 * sin_lambda, cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda);
+            cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+
+            // the same point
+            if sin_sigma == 0.0 && cos_sigma > 0.0 {
+                return 0.0;
+            }
+
+            sigma = f64::atan2(sin_sigma, cos_sigma);
+
+            let sin_alpha = if sin_sigma == 0.0 { 0.0 } else { cos_u1 * cos_u2 * sin_lambda / sin_sigma };
+            cos2_alpha = 1.0 - sin_alpha * sin_alpha;
+
+            // both points on the equator => cos2_alpha = 0
+            cos_2sigma_m = if cos2_alpha == 0.0 { 0.0 } else { cos_sigma - 2.0 * sin_u1 * sin_u2 / cos2_alpha };
+
+            let c = f / 16.0 * cos2_alpha * (4.0 + f * (4.0 - 3.0 * cos2_alpha));
+            let next_lambda = d_lon + (1.0 - c) * f * sin_alpha * (sigma + c * sin_sigma * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)));
+
+            let change = f64::abs(next_lambda - lambda);
+            lambda = next_lambda;
+            if change < Self::TOLERANCE {
+                converged = true;
+                break;
+            }
+        }
+
+        let u_squared = cos2_alpha * (a * a - b * b) / (b * b);
+        let big_a = 1.0 + u_squared / 16384.0 * (4096.0 + u_squared * (-768.0 + u_squared * (320.0 - 175.0 * u_squared)));
+        let big_b = u_squared / 1024.0 * (256.0 + u_squared * (-128.0 + u_squared * (74.0 - 47.0 * u_squared)));
+        let delta_sigma = big_b * sin_sigma * (cos_2sigma_m + big_b / 4.0 * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)
+            - big_b / 6.0 * cos_2sigma_m * (-3.0 + 4.0 * sin_sigma * sin_sigma) * (-3.0 + 4.0 * cos_2sigma_m * cos_2sigma_m)));
+
+        let distance = b * big_a * (sigma - delta_sigma);
+
+        if converged && distance.is_finite() {
+            return distance;
+        }
+
+        // not converged => keep the result consistent with the bounds used to prune the tree
+        let angle = SphereHelper::distance(p1, p2, 1.0);
+        if !distance.is_finite() {
+            return angle * Self::min_radius();
+        }
+        return f64::clamp(distance, angle * Self::min_radius(), angle * Self::max_radius());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use rand::Rng;
+
+    use crate::{distance_metric::Vincenty, geo_point::Point, sphere_helper::SphereHelper};
+    use crate::test_helper::{assert_tree_matches_brute_force, centers, clustered_points, point_in, seeded};
+    use super::EllipsoidHelper;
+
+    // degrees, minutes and seconds with the sign of the degrees
+    fn dms(degrees: f64, minutes: f64, seconds: f64) -> f64 {
+        return f64::copysign(f64::abs(degrees) + minutes / 60.0 + seconds / 3600.0, degrees);
+    }
+
+    #[test]
+    fn distance_matches_reference_values() {
+        // Flinders Peak - Buninyong, the example of Vincenty's paper https://geodesyapps.ga.gov.au/vincenty-inverse
+        let flinders_peak = Point::from_degrees(0, dms(-37.0, 57.0, 3.72030), dms(144.0, 25.0, 29.52440));
+        let buninyong = Point::from_degrees(1, dms(-37.0, 39.0, 10.15610), dms(143.0, 55.0, 35.38390));
+        let distance = EllipsoidHelper::distance(&flinders_peak, &buninyong);
+        assert!(f64::abs(distance - 54.972271) < 1e-6, "{}", distance);
+
+        // nearly antipodal points => many iterations
+        let distance = EllipsoidHelper::distance(&Point::from_degrees(0, 0.0, 0.0), &Point::from_degrees(1, 0.5, 179.5));
+        assert!(f64::abs(distance - 19936.288579) < 1e-6, "{}", distance);
+
+        // the same points and a quarter of the equator
+        assert_eq!(EllipsoidHelper::distance(&buninyong, &buninyong), 0.0);
+        let distance = EllipsoidHelper::distance(&Point::from_degrees(0, 0.0, 0.0), &Point::from_degrees(1, 0.0, 90.0));
+        assert!(f64::abs(distance - EllipsoidHelper::SEMI_MAJOR_AXIS * FRAC_PI_2) < 1e-6, "{}", distance);
+    }
+
+    #[test]
+    fn distance_is_between_spheres_of_min_and_max_radius() {
+        let mut rng = seeded(31);
+        let points = clustered_points(&mut rng, 2_000);
+
+        for first in points.iter() {
+            let second = &points[rng.gen_range(0..points.len())];
+            let distance = EllipsoidHelper::distance(first, second);
+            let lower = SphereHelper::distance(first, second, EllipsoidHelper::min_radius());
+            let upper = SphereHelper::distance(first, second, EllipsoidHelper::max_radius());
+
+            assert!(lower - 1e-9 <= distance && distance <= upper + 1e-9, "{:?} {:?}: {} not in [{}, {}]", first, second, distance, lower, upper);
+        }
+    }
+
+    #[test]
+    fn tree_with_vincenty_matches_brute_force() {
+        let mut rng = seeded(37);
+        let mut points = clustered_points(&mut rng, 4_000);
+
+        // points next to the centers => distances close to the search distances
+        for (idx, center) in centers().iter().enumerate() {
+            let lat_range = (f64::max(center.lat - 0.01, -FRAC_PI_2), f64::min(center.lat + 0.01, FRAC_PI_2));
+            let lon_range = (center.lon - 0.01, center.lon + 0.01);
+            points.extend((0..50).map(|k| point_in(&mut rng, 4_000 + 50 * idx + k, lat_range, lon_range)));
+        }
+
+        assert_tree_matches_brute_force(Vincenty, &points, &centers(), &[0.0, 10.0, 100.0, 1_000.0, 5_000.0]);
+    }
+}
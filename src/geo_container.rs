@@ -1,86 +1,183 @@
 use num_traits::ToPrimitive;
 use serde::{Deserialize, Serialize};
 
-use super::{geo_search_trait::GeoSearch, sphere_helper::SphereHelper, geo_point::Point};
+use super::{distance_metric::{DistanceMetric, Haversine}, geo_error::GeoError, geo_search_trait::GeoSearch, sphere_helper::SphereHelper, geo_point::{Locatable, Point}, relation::{Node, Relation}};
 
 #[derive(Debug, Serialize, Deserialize)]
 /// Data structure for solving geosearch problem in case of small amount of points
 /// In this case simple iterative search is applied
-pub struct GeoContainer {
+pub struct GeoContainer<T = Point, M = Haversine> {
     /// Id of the globe the data structure was constructed for
     globe: u64,
-    /// Radius of the globe. Measure unit = km.
-    sphere_radius: f64,
-    /// Geopoints that belong to the globe
-    points: Vec::<Point>,
+    /// Way to measure distances on the globe, e.g. haversine on a sphere of a radius (measure unit = km)
+    metric: M,
+    /// Geopoints that belong to the globe, possibly with a payload
+    points: Vec::<T>,
 }
 
-impl GeoContainer {
+impl<T: Locatable + Clone, M: DistanceMetric> GeoContainer<T, M> {
     /// Create a new instance of GeoContainer
     /// 
     /// ## Arguments
     /// 
-    /// * 'points' - Geopoints that belong to the globe
-    /// * 'sphere_radius' - Radius of the globe. Measure unit = km.
+    /// * 'points' - Geopoints that belong to the globe. Can be plain points or points with a payload, e.g. (Point, weight)
+    /// * 'metric' - Distance metric, e.g. Haversine { radius } with the radius of the globe (measure unit = km) or Vincenty
     /// * 'globe' - Id of the globe the data structure was constructed for
     /// 
     /// ## Returns
     /// 
-    /// * 'container' - New instance of GeoContainer 
-    pub fn new(points: &Vec::<Point>, sphere_radius: f64, globe: u64) -> Self {
+    /// * 'container' - New instance of GeoContainer or an error if a point or the radius is invalid
+    pub fn new(points: &[T], metric: M, globe: u64) -> Result<Self, GeoError> {
 
-        let data_points = points.iter().map(|point| point.clone()).collect();
+        metric.validate()?;
+        for item in points {
+            item.point().validate()?;
+        }
 
-        return GeoContainer {
-            globe: globe,
-            sphere_radius: sphere_radius,
+        let data_points = points.to_vec();
+
+        return Ok(GeoContainer {
+            globe,
+            metric,
             points: data_points,
-        };
+        });
     }
-}
 
-impl GeoSearch for GeoContainer {
-    fn search_by_distance<R: Relation + FromIterator<Vec<Node>>>(&self, point: &Point, distance: f64) -> R {
-        // Filter candidates and get the answer
-        let results = self.points.iter().filter(|candidate| {
-            SphereHelper::distance(point, candidate, self.sphere_radius) <= distance
-        }).map(|candidate| {
+    /// Find all stored items within the distance around the point
+    ///
+    /// ## Arguments
+    ///
+    /// * 'point' - Center of the search
+    /// * 'distance' - Search radius. Measure unit = km.
+    ///
+    /// ## Returns
+    ///
+    /// * 'items' - Iterator over stored items together with their distance to the center or an error if the input is invalid
+    pub fn search_items_by_distance(&self, point: &Point, distance: f64) -> Result<impl Iterator<Item = (&T, f64)> + '_, GeoError> {
 
-            // Return point and distance to this point in km.
-            let distance_to_candidate = SphereHelper::distance(point, &candidate, self.sphere_radius).to_f32().unwrap();
+        point.validate()?;
+        SphereHelper::validate_distance(distance)?;
 
-            // Return a relation with 2 columns => (Node.id, Distance)
-            let mut vec = Vec::with_capacity(2);
-            vec.push(candidate.node);
-            vec.push(from_number_float(distance_to_candidate, NodeType::NumberFloat));
-            return vec;
-        }).collect();
+        // Filter candidates and get the answer
+        let center = *point;
 
-        return results;
+        // Filter candidates lazily
+        let results = self.points.iter().map(move |candidate| {
+            // Calculate distance to each point only once
+            (candidate, self.metric.distance(&center, candidate.point()))
+        }).filter(move |&(_, distance_to_candidate)| {
+            distance_to_candidate <= distance
+        });
+
+        return Ok(results);
     }
 
-    fn search_by_box<R: Relation + FromIterator<Vec<Node>>>(&self, corner_west: &Point, corner_east: &Point) -> R {
-        
+    /// Find all stored items inside the box between two corners
+    ///
+    /// ## Arguments
+    ///
+    /// * 'corner_west' - South-west corner of the box (cornerWest in Wikidata)
+    /// * 'corner_east' - North-east corner of the box (cornerEast in Wikidata)
+    ///
+    /// ## Returns
+    ///
+    /// * 'items' - Iterator over stored items inside the box or an error if a corner is invalid
+    pub fn search_items_by_box(&self, corner_west: &Point, corner_east: &Point) -> Result<impl Iterator<Item = &T> + '_, GeoError> {
+
         // Identify SearchBoxes for the target
-        let (box_0, box_1) = SphereHelper::construct_searchbox(corner_west, corner_east);
+        let (box_0, box_1) = SphereHelper::construct_searchbox(corner_west, corner_east)?;
 
-        // Filter candidates and get the answer
-        let results = self.points.iter().filter(|candidate| {
+        // Filter candidates lazily
+        let results = self.points.iter().filter(move |candidate| {
             match (box_0, box_1) {
                 (Some(target_0), Some(target_1)) => {
-                    target_0.is_inside(&candidate) || target_1.is_inside(&candidate)
+                    target_0.is_inside(candidate.point()) || target_1.is_inside(candidate.point())
                 },
                 (Some(target_0), None) => {
-                    target_0.is_inside(&candidate)
+                    target_0.is_inside(candidate.point())
                 },
                 _ => {false}
             }
-        }).map(|point| {
-            let mut vec = Vec::with_capacity(1);
-            vec.push(point.node);
-            return vec;
+        });
+
+        return Ok(results);
+    }
+}
+
+impl<T: Locatable + Clone, M: DistanceMetric> GeoSearch for GeoContainer<T, M> {
+    fn search_by_distance<R: Relation>(&self, point: &Point, distance: f64) -> Result<R, GeoError> {
+        return self.search_items_by_distance(point, distance)?.map(|(candidate, distance_to_candidate)| {
+
+            // Return point and distance to this point in km.
+            let distance_to_candidate = distance_to_candidate.to_f32().ok_or(GeoError::ValueOverflow(distance_to_candidate))?;
+
+            // Return a relation with 2 columns => (Node.id, Distance)
+            return Ok(vec![Node::Id(candidate.point().id), Node::NumberFloat(distance_to_candidate)]);
         }).collect();
+    }
+
+    fn search_by_box<R: Relation>(&self, corner_west: &Point, corner_east: &Point) -> Result<R, GeoError> {
+        return Ok(self.search_items_by_box(corner_west, corner_east)?.map(|candidate| {
+            return vec![Node::Id(candidate.point().id)];
+        }).collect());
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use crate::{distance_metric::{DistanceMetric, Haversine, Vincenty}, geo_point::Point, geo_search_trait::GeoSearch, kd_tree::KDTree, relation::Node};
+    use crate::test_helper::{centers, clustered_points, seeded, RADIUS};
+    use super::GeoContainer;
 
-        return results;
+    // rows of a relation ordered by the id in the first column
+    fn sorted(mut rows: Vec<Vec<Node>>) -> Vec<Vec<Node>> {
+        rows.sort_by_key(|row| match row[0] {
+            Node::Id(id) => id,
+            Node::NumberFloat(_) => panic!("first column must be the id")
+        });
+        return rows;
     }
-}
\ No newline at end of file
+
+    // both structures must give the same rows, including distances, for any input
+    fn assert_same_rows<M: DistanceMetric>(metric: M) {
+        let points = clustered_points(&mut seeded(73), 3_000);
+        let container = GeoContainer::new(&points, metric, 0).unwrap();
+        let tree = KDTree::new(&points, 8, metric).unwrap();
+
+        for center in centers() {
+            for distance in [0.0, 20.0, 700.0, 6_000.0, 30_000.0] {
+                let from_container: Vec<Vec<Node>> = container.search_by_distance(&center, distance).unwrap();
+                let from_tree: Vec<Vec<Node>> = GeoSearch::search_by_distance(&tree, &center, distance).unwrap();
+                assert_eq!(sorted(from_tree), sorted(from_container), "{:?} around {:?} within {}", metric, center, distance);
+            }
+        }
+
+        // (west, east): a plain box, boxes over the antimeridian and corners on the poles
+        let corner = |lat: f64, lon: f64| Point { id: 0, lat, lon };
+        let boxes = [
+            (corner(-0.5, 0.2), corner(0.7, 1.1)),
+            (corner(-0.8, 3.0), corner(0.9, -3.0)),
+            (corner(PI / 2.0, -1.0), corner(PI / 2.0 - 0.03, 2.0)),
+            (corner(-PI / 2.0, 2.5), corner(-1.0, -2.5)),
+            (corner(-PI / 2.0, -PI), corner(PI / 2.0, PI))
+        ];
+        for (west, east) in boxes {
+            let from_container: Vec<Vec<Node>> = container.search_by_box(&west, &east).unwrap();
+            let from_tree: Vec<Vec<Node>> = GeoSearch::search_by_box(&tree, &west, &east).unwrap();
+            assert_eq!(sorted(from_tree), sorted(from_container), "{:?}: box from {:?} to {:?}", metric, west, east);
+        }
+
+        // invalid input => the same errors
+        let invalid = corner(2.0, 0.0);
+        assert_eq!(GeoSearch::search_by_distance::<Vec<Vec<Node>>>(&tree, &invalid, 1.0), container.search_by_distance::<Vec<Vec<Node>>>(&invalid, 1.0));
+        assert_eq!(GeoSearch::search_by_distance::<Vec<Vec<Node>>>(&tree, &centers()[0], -1.0), container.search_by_distance::<Vec<Vec<Node>>>(&centers()[0], -1.0));
+        assert_eq!(GeoSearch::search_by_box::<Vec<Vec<Node>>>(&tree, &invalid, &centers()[0]), container.search_by_box::<Vec<Vec<Node>>>(&invalid, &centers()[0]));
+    }
+
+    #[test]
+    fn container_and_tree_give_the_same_rows() {
+        assert_same_rows(Haversine::new(RADIUS));
+        assert_same_rows(Vincenty);
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+// Errors of construction and queries. Every variant carries the value that caused it
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoError {
+    MissingCoordinate { id: usize, coordinate: &'static str }, // builder was not given latitude or longitude
+    NonFiniteCoordinate { id: usize, lat: f64, lon: f64 }, // NaN or infinite coordinates
+    LatitudeOutOfRange { id: usize, lat: f64 }, // latitude must be in [-PI / 2, PI / 2]
+    LongitudeOutOfRange { id: usize, lon: f64 }, // longitude must be in [-PI, PI]
+    InvalidDistance(f64), // search distance must be finite and not negative
+    InvalidRadius(f64), // sphere radius must be finite and positive
+    InvalidLeafSize(usize), // n_stop must be at least 1
+    DuplicateId(usize), // points of a tree are found, removed and updated by id => ids must be unique
+    OutOfDomain { function: &'static str, value: f64 }, // argument outside of the domain of a math function
+    ValueOverflow(f64), // value can not be represented in the output type
+    InvalidRing { vertices: usize }, // ring of a polygon needs at least 3 different vertices
+    Io(String), // reading or writing a file failed
+    InvalidFormat(String), // saved data is broken or not a saved index
+    UnsupportedVersion { found: u32, expected: u32 }, // saved with another version of the format
+    ChecksumMismatch { found: u32, expected: u32 }, // saved data was modified or damaged
+    MetricMismatch { found: String, expected: String }, // index was built for another distance metric
+    InvalidGeohash { hash: String, position: usize }, // character of a geohash is not in the base32 alphabet
+    InvalidGeohashLength(usize), // geohash must have 1 to 12 characters
+    InvalidWkt { position: usize, message: String }, // WKT literal can not be parsed, position = byte offset in the text
+    InvalidGeoJson(String), // GeoJSON is broken or does not contain a FeatureCollection of points
+    MissingColumn(String), // header of a CSV file does not have the column
+    MalformedRow { line: u64, message: String }, // row of a CSV file can not be turned into a point
+}
+
+impl fmt::Display for GeoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeoError::MissingCoordinate { id, coordinate } => write!(f, "{} of point {} is not set", coordinate, id),
+            GeoError::NonFiniteCoordinate { id, lat, lon } => write!(f, "coordinates of point {} are not finite: lat = {}, lon = {}", id, lat, lon),
+            GeoError::LatitudeOutOfRange { id, lat } => write!(f, "latitude of point {} is out of range [-PI / 2, PI / 2]: {} (coordinates must be in radians)", id, lat),
+            GeoError::LongitudeOutOfRange { id, lon } => write!(f, "longitude of point {} is out of range [-PI, PI]: {} (coordinates must be in radians)", id, lon),
+            GeoError::InvalidDistance(distance) => write!(f, "search distance must be finite and not negative: {}", distance),
+            GeoError::InvalidRadius(radius) => write!(f, "sphere radius must be finite and positive: {}", radius),
+            GeoError::InvalidLeafSize(n_stop) => write!(f, "leaf size n_stop must be at least 1: {}", n_stop),
+            GeoError::DuplicateId(id) => write!(f, "id {} is used by more than one point", id),
+            GeoError::OutOfDomain { function, value } => write!(f, "{} is not defined for {}", function, value),
+            GeoError::ValueOverflow(value) => write!(f, "value {} does not fit into the output type", value),
+            GeoError::InvalidRing { vertices } => write!(f, "ring of a polygon needs at least 3 vertices, got {}", vertices),
+            GeoError::Io(message) => write!(f, "io error: {}", message),
+            GeoError::InvalidFormat(message) => write!(f, "invalid format: {}", message),
+            GeoError::UnsupportedVersion { found, expected } => write!(f, "format version {} is not supported, expected {}", found, expected),
+            GeoError::ChecksumMismatch { found, expected } => write!(f, "checksum {:#010x} does not match stored {:#010x}, data is damaged", found, expected),
+            GeoError::MetricMismatch { found, expected } => write!(f, "index was built for {}, expected {}", found, expected),
+            GeoError::InvalidGeohash { hash, position } => write!(f, "invalid character at position {} of geohash {:?}", position, hash),
+            GeoError::InvalidGeohashLength(length) => write!(f, "geohash must have 1 to 12 characters, got {}", length),
+            GeoError::InvalidWkt { position, message } => write!(f, "invalid WKT at position {}: {}", position, message),
+            GeoError::InvalidGeoJson(message) => write!(f, "invalid GeoJSON: {}", message),
+            GeoError::MissingColumn(name) => write!(f, "CSV header has no column {:?}", name),
+            GeoError::MalformedRow { line, message } => write!(f, "malformed CSV row at line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for GeoError {}
+
+impl From<std::io::Error> for GeoError {
+    fn from(error: std::io::Error) -> Self {
+        return GeoError::Io(error.to_string());
+    }
+}
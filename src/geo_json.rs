@@ -0,0 +1,336 @@
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use std::io::{Read, Write};
+
+use geojson::{feature::Id, Feature, FeatureCollection, GeoJson, Geometry, JsonObject, Value};
+
+use crate::{geo_error::GeoError, geo_point::Point, search_box::SearchBox, sphere_helper::SphereHelper};
+
+/*
+GeoJSON https://datatracker.ietf.org/doc/html/rfc7946
+Positions are [longitude, latitude] in degrees, the crate's points are in radians.
+Reading gives points together with properties of their features => they can be indexed as (Point, JsonObject).
+Writing is meant for inspection of queries in a map viewer: results, radius circles and SearchBoxes become features
+*/
+
+// anything that can be written as a GeoJSON feature
+pub trait ToFeature {
+    fn to_feature(&self) -> Feature;
+}
+
+// Point geometry, id of the point is the id of the feature
+impl ToFeature for Point {
+    fn to_feature(&self) -> Feature {
+        return Feature {
+            bbox: None,
+            geometry: Some(Geometry::new(Value::Point(position(self)))),
+            id: Some(Id::Number(self.id.into())),
+            properties: None,
+            foreign_members: None
+        };
+    }
+}
+
+// point read by read_points => its properties are written back
+impl ToFeature for (Point, JsonObject) {
+    fn to_feature(&self) -> Feature {
+        return Feature { properties: Some(self.1.clone()), ..self.0.to_feature() };
+    }
+}
+
+// Polygon geometry of the box
+impl ToFeature for SearchBox {
+    fn to_feature(&self) -> Feature {
+        let (west, east) = (self.lon_from.to_degrees(), self.lon_to.to_degrees());
+        let (south, north) = (self.lat_from.to_degrees(), self.lat_to.to_degrees());
+        let ring = vec![vec![west, south], vec![east, south], vec![east, north], vec![west, north], vec![west, south]];
+        return Feature {
+            bbox: None,
+            geometry: Some(Geometry::new(Value::Polygon(vec![ring]))),
+            id: None,
+            properties: None,
+            foreign_members: None
+        };
+    }
+}
+
+// points of a FeatureCollection with properties of their features (empty if there are none)
+// id of a point = id of its feature, features without id get their position in the collection.
+// Ids must be non-negative integers and unique => other ids are rejected instead of being replaced by positions that can collide
+pub fn read_points<R: Read>(reader: R) -> Result<Vec::<(Point, JsonObject)>, GeoError> {
+
+    let collection = match GeoJson::from_reader(reader) {
+        Ok(GeoJson::FeatureCollection(collection)) => collection,
+        Ok(_) => return Err(GeoError::InvalidGeoJson("expected a FeatureCollection".to_string())),
+        Err(error) => return Err(GeoError::InvalidGeoJson(error.to_string()))
+    };
+
+    let mut ids = HashSet::with_capacity(collection.features.len());
+    let mut points = Vec::with_capacity(collection.features.len());
+    for (idx, feature) in collection.features.into_iter().enumerate() {
+
+        let coordinates = match feature.geometry.as_ref().map(|geometry| &geometry.value) {
+            Some(Value::Point(coordinates)) => coordinates,
+            Some(value) => return Err(GeoError::InvalidGeoJson(format!("feature {}: expected Point geometry, found {}", idx, value.type_name()))),
+            None => return Err(GeoError::InvalidGeoJson(format!("feature {}: geometry is missing", idx)))
+        };
+
+        // a third value (altitude) is allowed and ignored
+        let (lon, lat) = match coordinates.as_slice() {
+            [lon, lat, ..] => (*lon, *lat),
+            _ => return Err(GeoError::InvalidGeoJson(format!("feature {}: position needs longitude and latitude", idx)))
+        };
+        if !(-180.0..=180.0).contains(&lon) || !(-90.0..=90.0).contains(&lat) {
+            return Err(GeoError::InvalidGeoJson(format!("feature {}: position [{}, {}] is out of range [-180, 180] x [-90, 90]", idx, lon, lat)));
+        }
+
+        let id = match &feature.id {
+            None => idx,
+            Some(Id::Number(number)) => match number.as_u64().and_then(|id| usize::try_from(id).ok()) {
+                Some(id) => id,
+                None => return Err(GeoError::InvalidGeoJson(format!("feature {}: id {} is not a non-negative integer", idx, number)))
+            },
+            Some(Id::String(id)) => return Err(GeoError::InvalidGeoJson(format!("feature {}: id {:?} is not a non-negative integer", idx, id)))
+        };
+        if !ids.insert(id) {
+            return Err(GeoError::InvalidGeoJson(format!("feature {}: id {} is already used by another feature", idx, id)));
+        }
+
+        points.push((Point::from_degrees(id, lat, lon), feature.properties.unwrap_or_default()));
+    }
+
+    return Ok(points);
+}
+
+// results of a radius search (item, distance to the center), the distance is added to properties as "distance"
+pub fn search_results<T: ToFeature>(results: &[(T, f64)]) -> Vec::<Feature> {
+    return results.iter().map(|(item, distance)| {
+        let mut feature = item.to_feature();
+        feature.set_property("distance", *distance);
+        feature
+    }).collect();
+}
+
+/*
+Circle of the distance around the point on the sphere of the radius as a polygon with the given number of vertices.
+Longitude wraps around the antimeridian => the circle is split in two polygons there, like boxes of find_box.
+If the circle contains a pole, its border goes along the antimeridian to the pole and back
+*/
+pub fn circle(center: &Point, distance: f64, radius: f64, segments: usize) -> Result<Feature, GeoError> {
+
+    center.validate()?;
+    SphereHelper::validate_distance(distance)?;
+    SphereHelper::validate_radius(radius)?;
+
+    let angle = f64::min(distance / radius, PI);
+    let segments = usize::max(segments, 3);
+
+    // bearings go counterclockwise => the exterior ring is counterclockwise as RFC 7946 recommends
+    let vertices: Vec::<Point> = (0..segments).map(|idx| destination(center, -2.0 * PI * idx as f64 / segments as f64, angle)).collect();
+
+    // vertices in degrees, longitude without jumps over the antimeridian
+    let mut ring = Vec::with_capacity(segments + 3);
+    let mut lon = vertices[0].lon;
+    ring.push([lon.to_degrees(), vertices[0].lat_degrees()]);
+    for (previous, vertex) in vertices.iter().zip(vertices.iter().skip(1)) {
+        lon += SphereHelper::normalize_lon(vertex.lon - previous.lon);
+        ring.push([lon.to_degrees(), vertex.lat_degrees()]);
+    }
+    let lon = lon.to_degrees();
+
+    // longitude made a full turn => the circle contains a pole, close the ring through it
+    let first_lon = ring[0][0];
+    if f64::abs(lon - first_lon) > 180.0 {
+        let pole = if center.lat >= 0.0 { 90.0 } else { -90.0 };
+        let last_lon = first_lon + f64::signum(lon - first_lon) * 360.0;
+        ring.extend([[last_lon, ring[0][1]], [last_lon, pole], [first_lon, pole]]);
+    }
+
+    // parts of the ring inside [-540, -180], [-180, 180] and [180, 540], moved to [-180, 180]
+    let parts: Vec::<Vec::<Vec::<f64>>> = [-360.0, 0.0, 360.0].iter().filter_map(|&shift| {
+        let part = clip_lon(&clip_lon(&ring, shift - 180.0, true), shift + 180.0, false);
+
+        // the ring only touches the border of the range (along the antimeridian up to a pole and back) => nothing to draw
+        if part.len() < 3 || f64::abs(ring_area(&part)) < 1e-9 {
+            return None;
+        }
+        let mut part: Vec::<Vec::<f64>> = part.iter().map(|[lon, lat]| vec![lon - shift, *lat]).collect();
+        part.push(part[0].clone());
+        return Some(part);
+    }).collect();
+
+    let value = if parts.len() == 1 {
+        Value::Polygon(parts)
+    } else {
+        Value::MultiPolygon(parts.into_iter().map(|part| vec![part]).collect())
+    };
+
+    let mut feature = Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(value)),
+        id: None,
+        properties: None,
+        foreign_members: None
+    };
+    feature.set_property("distance", distance);
+    return Ok(feature);
+}
+
+// write features as a FeatureCollection
+pub fn write_features<W: Write>(writer: W, features: Vec::<Feature>) -> Result<(), GeoError> {
+    let collection = FeatureCollection { bbox: None, features, foreign_members: None };
+    return serde_json::to_writer(writer, &collection).map_err(|error| GeoError::Io(error.to_string()));
+}
+
+fn position(point: &Point) -> Vec::<f64> {
+    return vec![point.lon_degrees(), point.lat_degrees()];
+}
+
+// point at the angle (in radians of the great circle) from the start in the direction of the bearing
+fn destination(start: &Point, bearing: f64, angle: f64) -> Point {
+
+    // at a pole every direction is south (north) => the bearing is measured from the meridian of the start
+    if f64::cos(start.lat) < 1e-12 {
+        let lon = if start.lat > 0.0 { start.lon + PI - bearing } else { start.lon + bearing };
+        return Point { id: start.id, lat: f64::signum(start.lat) * (PI / 2.0 - angle), lon: SphereHelper::normalize_lon(lon) };
+    }
+
+    let lat = f64::asin(f64::clamp(f64::sin(start.lat) * f64::cos(angle) + f64::cos(start.lat) * f64::sin(angle) * f64::cos(bearing), -1.0, 1.0));
+    let lon = start.lon + f64::atan2(f64::sin(bearing) * f64::sin(angle) * f64::cos(start.lat), f64::cos(angle) - f64::sin(start.lat) * f64::sin(lat));
+    return Point { id: start.id, lat, lon: SphereHelper::normalize_lon(lon) };
+}
+
+// part of the ring on one side of the meridian (Sutherland-Hodgman). east => keep longitudes >= lon, otherwise <= lon
+fn clip_lon(ring: &[[f64; 2]], lon: f64, east: bool) -> Vec::<[f64; 2]> {
+
+    let inside = |vertex: &[f64; 2]| if east { vertex[0] >= lon } else { vertex[0] <= lon };
+
+    let mut clipped = Vec::with_capacity(ring.len() + 2);
+    for (idx, current) in ring.iter().enumerate() {
+        let previous = &ring[(idx + ring.len() - 1) % ring.len()];
+        if inside(current) != inside(previous) {
+            let t = (lon - previous[0]) / (current[0] - previous[0]);
+            clipped.push([lon, previous[1] + t * (current[1] - previous[1])]);
+        }
+        if inside(current) {
+            clipped.push(*current);
+        }
+    }
+
+    return clipped;
+}
+
+// signed area of the ring in square degrees (shoelace formula). 0 if all vertices are on a line or the same
+fn ring_area(ring: &[[f64; 2]]) -> f64 {
+    return ring.iter().zip(ring.iter().cycle().skip(1)).map(|(current, next)| current[0] * next[1] - next[0] * current[1]).sum::<f64>() / 2.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use geojson::{Feature, Value};
+
+    use crate::{geo_error::GeoError, geo_point::Point, sphere_helper::SphereHelper};
+    use super::{circle, read_points, ring_area};
+
+    const RADIUS: f64 = 6371.0;
+
+    // rings of all polygons of the feature
+    fn parts(feature: &Feature) -> Vec<Vec<Vec<f64>>> {
+        match feature.geometry.as_ref().map(|geometry| &geometry.value) {
+            Some(Value::Polygon(rings)) => return rings.clone(),
+            Some(Value::MultiPolygon(polygons)) => return polygons.iter().flatten().cloned().collect(),
+            other => panic!("{:?} is not a polygon", other)
+        }
+    }
+
+    // every part is closed and covers some area. Returns the sum of the longitude ranges of the parts
+    fn assert_parts_are_not_empty(center: &Point, feature: &Feature) -> f64 {
+        let mut lon_extent = 0.0;
+        for part in parts(feature) {
+            assert!(part.len() >= 4 && part.first() == part.last(), "{:?}: part {:?} is not closed", center, part);
+
+            let vertices: Vec<[f64; 2]> = part.iter().map(|vertex| [vertex[0], vertex[1]]).collect();
+            assert!(f64::abs(ring_area(&vertices)) > 1e-6, "{:?}: part {:?} has no area", center, part);
+
+            let (west, east) = vertices.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(west, east), vertex| (f64::min(west, vertex[0]), f64::max(east, vertex[0])));
+            assert!(-180.0 <= west && east <= 180.0, "{:?}: part {:?} is out of range", center, part);
+            lon_extent += east - west;
+        }
+        return lon_extent;
+    }
+
+    #[test]
+    fn circles_around_poles_have_no_empty_parts() {
+        for (lat, lon) in [(89.0, 0.0), (90.0, 0.0), (-90.0, 30.0), (-89.0, -179.9), (88.0, 179.0)] {
+            let center = Point::from_degrees(0, lat, lon);
+            let feature = circle(&center, 500.0, RADIUS, 16).unwrap();
+
+            // the circle contains the pole => its parts go around the whole sphere and up to the pole
+            let lon_extent = assert_parts_are_not_empty(&center, &feature);
+            assert!(f64::abs(lon_extent - 360.0) < 1e-9, "{:?}: parts cover {} degrees of longitude", center, lon_extent);
+            assert!(parts(&feature).iter().flatten().any(|vertex| vertex[1] == 90.0 * f64::signum(lat)), "{:?}: the pole is not reached", center);
+        }
+    }
+
+    #[test]
+    fn circle_across_antimeridian_is_split() {
+        let center = Point::from_degrees(0, 10.0, 179.5);
+        let feature = circle(&center, 200.0, RADIUS, 32).unwrap();
+        assert_parts_are_not_empty(&center, &feature);
+
+        // one part on each side of the antimeridian, both end at it
+        let parts = parts(&feature);
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].iter().all(|vertex| vertex[0] > 0.0) && parts[0].iter().any(|vertex| vertex[0] == 180.0));
+        assert!(parts[1].iter().all(|vertex| vertex[0] < 0.0) && parts[1].iter().any(|vertex| vertex[0] == -180.0));
+
+        // vertices are on the circle, vertices added on the antimeridian are inside it
+        for vertex in parts.iter().flatten() {
+            let distance = SphereHelper::distance(&center, &Point::from_degrees(0, vertex[1], vertex[0]), RADIUS);
+            assert!(distance <= 200.0 + 1e-6, "{:?} is {} km away", vertex, distance);
+            assert!(f64::abs(vertex[0]) == 180.0 || distance > 200.0 - 1e-6, "{:?} is {} km away", vertex, distance);
+        }
+
+        // far from the antimeridian and the poles the circle is one polygon
+        let feature = circle(&Point::from_degrees(0, 48.1371, 11.5754), 60.0, RADIUS, 16).unwrap();
+        assert!(matches!(feature.geometry.unwrap().value, Value::Polygon(_)));
+    }
+
+    #[test]
+    fn read_points_takes_ids_and_properties() {
+        let text = r#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "id": 7, "geometry": {"type": "Point", "coordinates": [11.5754, 48.1371]}, "properties": {"name": "Munich"}},
+            {"type": "Feature", "geometry": {"type": "Point", "coordinates": [-180.0, -90.0, 12.0]}, "properties": null}
+        ]}"#;
+
+        let points = read_points(text.as_bytes()).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].0.id, 7);
+        assert!(f64::abs(points[0].0.lat_degrees() - 48.1371) < 1e-12 && f64::abs(points[0].0.lon_degrees() - 11.5754) < 1e-12);
+        assert_eq!(points[0].1["name"], "Munich");
+
+        // no id => position in the collection
+        assert_eq!(points[1].0.id, 1);
+        assert!(points[1].1.is_empty());
+    }
+
+    #[test]
+    fn read_points_rejects_ids_that_are_not_unique_non_negative_integers() {
+        let collection = |ids: [&str; 2]| {
+            let features: Vec<String> = ids.iter().enumerate().map(|(idx, id)| {
+                let id = if id.is_empty() { String::new() } else { format!(r#""id": {}, "#, id) };
+                format!(r#"{{"type": "Feature", {}"geometry": {{"type": "Point", "coordinates": [{}, 0]}}, "properties": {{}}}}"#, id, idx)
+            }).collect();
+            return format!(r#"{{"type": "FeatureCollection", "features": [{}]}}"#, features.join(", "));
+        };
+
+        for ids in [["1", r#""x""#], ["1", "-2"], ["1", "2.5"], ["3", "3"], ["1", ""]] {
+            match read_points(collection(ids).as_bytes()) {
+                Err(GeoError::InvalidGeoJson(message)) => assert!(message.starts_with("feature 1: id"), "{:?}: {}", ids, message),
+                other => panic!("{:?} are read as {:?}", ids, other)
+            }
+        }
+
+        assert!(read_points(collection(["2", ""]).as_bytes()).is_ok());
+    }
+}
@@ -1,8 +1,208 @@
 // Struct of point. In the implementation for DB here will be longitude (x), latitude (y)
 
-#[derive(Debug, Clone, Copy)]
+use std::f64::consts::PI;
+use serde::{Deserialize, Serialize};
+
+use crate::{geo_error::GeoError, geohash::GeohashHelper, sphere_helper::SphereHelper, wkt::Geometry};
+
+// coordinates are stored in radians, latitude in [-PI / 2, PI / 2], longitude in [-PI, PI]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Point {
     pub id: usize,
     pub lat: f64,
     pub lon: f64,
-}
\ No newline at end of file
+}
+
+impl Point {
+
+    // create a point from coordinates in degrees (the way they come in WKT and most datasets)
+    // coordinates are not checked: only PointBuilder validates, untrusted data should go through it
+    pub fn from_degrees(id: usize, lat: f64, lon: f64) -> Self {
+        return Point {
+            id,
+            lat: lat.to_radians(),
+            lon: lon.to_radians()
+        };
+    }
+
+    // start building a point with validation of coordinates
+    pub fn builder(id: usize) -> PointBuilder {
+        return PointBuilder::new(id);
+    }
+
+    // check that coordinates are finite and in range. Points in the tree must always be valid
+    pub fn validate(&self) -> Result<(), GeoError> {
+
+        if !self.lat.is_finite() || !self.lon.is_finite() {
+            return Err(GeoError::NonFiniteCoordinate { id: self.id, lat: self.lat, lon: self.lon });
+        }
+
+        if !(-PI / 2.0..=PI / 2.0).contains(&self.lat) {
+            return Err(GeoError::LatitudeOutOfRange { id: self.id, lat: self.lat });
+        }
+
+        if !(-PI..=PI).contains(&self.lon) {
+            return Err(GeoError::LongitudeOutOfRange { id: self.id, lon: self.lon });
+        }
+
+        return Ok(());
+    }
+
+    pub fn lat_degrees(&self) -> f64 {
+        return self.lat.to_degrees();
+    }
+
+    pub fn lon_degrees(&self) -> f64 {
+        return self.lon.to_degrees();
+    }
+
+    // geohash of the given length (see GeohashHelper)
+    pub fn to_geohash(&self, precision: usize) -> Result<String, GeoError> {
+        return GeohashHelper::encode(self, precision);
+    }
+
+    // point in the center of the geohash cell
+    pub fn from_geohash(id: usize, hash: &str) -> Result<Self, GeoError> {
+        return GeohashHelper::decode(id, hash);
+    }
+
+    // point of a WKT literal "Point(lon lat)" in degrees (see wkt)
+    pub fn from_wkt(id: usize, text: &str) -> Result<Self, GeoError> {
+        match Geometry::from_wkt(text)? {
+            Geometry::Point(point) => Ok(Point { id, ..point }),
+            _ => Err(GeoError::InvalidWkt { position: 0, message: "expected POINT".to_string() })
+        }
+    }
+
+    pub fn to_wkt(&self) -> String {
+        return Geometry::Point(*self).to_wkt();
+    }
+}
+
+/*
+Anything that has a position on the sphere and can be stored in an index.
+Indexes return the stored items => a payload (graph node, weight, category) comes back with the point
+*/
+pub trait Locatable {
+    fn point(&self) -> &Point;
+
+    fn point_mut(&mut self) -> &mut Point;
+}
+
+impl Locatable for Point {
+    fn point(&self) -> &Point {
+        return self;
+    }
+
+    fn point_mut(&mut self) -> &mut Point {
+        return self;
+    }
+}
+
+// point with a payload
+impl<P> Locatable for (Point, P) {
+    fn point(&self) -> &Point {
+        return &self.0;
+    }
+
+    fn point_mut(&mut self) -> &mut Point {
+        return &mut self.0;
+    }
+}
+
+/*
+Builder for points from untrusted data.
+Rejects NaN and latitude out of range, brings longitude into [-PI, PI]
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct PointBuilder {
+    id: usize,
+    lat: Option<f64>, // radians
+    lon: Option<f64>, // radians
+}
+
+impl PointBuilder {
+
+    pub fn new(id: usize) -> Self {
+        return PointBuilder {
+            id,
+            lat: None,
+            lon: None
+        };
+    }
+
+    // latitude in radians
+    pub fn lat(mut self, lat: f64) -> Self {
+        self.lat = Some(lat);
+        return self;
+    }
+
+    // longitude in radians
+    pub fn lon(mut self, lon: f64) -> Self {
+        self.lon = Some(lon);
+        return self;
+    }
+
+    pub fn lat_degrees(self, lat: f64) -> Self {
+        return self.lat(lat.to_radians());
+    }
+
+    pub fn lon_degrees(self, lon: f64) -> Self {
+        return self.lon(lon.to_radians());
+    }
+
+    pub fn build(self) -> Result<Point, GeoError> {
+
+        let lat = self.lat.ok_or(GeoError::MissingCoordinate { id: self.id, coordinate: "latitude" })?;
+        let lon = self.lon.ok_or(GeoError::MissingCoordinate { id: self.id, coordinate: "longitude" })?;
+
+        // normalization keeps NaN => it is caught by validation
+        let point = Point {
+            id: self.id,
+            lat,
+            lon: SphereHelper::normalize_lon(lon)
+        };
+        point.validate()?;
+
+        return Ok(point);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use crate::geo_error::GeoError;
+    use super::Point;
+
+    #[test]
+    fn builder_validates_coordinates() {
+        let point = Point::builder(7).lat_degrees(48.1371).lon_degrees(11.5754).build().unwrap();
+        assert_eq!((point.id, point.lat, point.lon), (7, 48.1371f64.to_radians(), 11.5754f64.to_radians()));
+
+        assert_eq!(Point::builder(1).lon(0.0).build().err(), Some(GeoError::MissingCoordinate { id: 1, coordinate: "latitude" }));
+        assert_eq!(Point::builder(2).lat(0.0).build().err(), Some(GeoError::MissingCoordinate { id: 2, coordinate: "longitude" }));
+        assert_eq!(Point::builder(3).lat(1.6).lon(0.0).build().err(), Some(GeoError::LatitudeOutOfRange { id: 3, lat: 1.6 }));
+        assert_eq!(Point::builder(4).lat_degrees(-90.0).lon(0.0).build().map(|point| point.lat), Ok(-PI / 2.0));
+
+        // NaN is not equal to itself => the error is matched by kind
+        assert!(matches!(Point::builder(5).lat(f64::NAN).lon(0.0).build(), Err(GeoError::NonFiniteCoordinate { id: 5, .. })));
+        assert!(matches!(Point::builder(6).lat(0.0).lon(f64::NAN).build(), Err(GeoError::NonFiniteCoordinate { id: 6, .. })));
+        assert!(matches!(Point::builder(8).lat(0.0).lon(f64::INFINITY).build(), Err(GeoError::NonFiniteCoordinate { id: 8, .. })));
+    }
+
+    #[test]
+    fn builder_normalizes_longitude() {
+        let lon = |degrees: f64| Point::builder(0).lat(0.0).lon_degrees(degrees).build().unwrap().lon_degrees();
+
+        // 180 and -180 are the same meridian
+        assert!(f64::abs(f64::abs(lon(540.0)) - 180.0) < 1e-9, "{}", lon(540.0));
+        assert!(f64::abs(lon(190.0) + 170.0) < 1e-9, "{}", lon(190.0));
+        assert!(f64::abs(lon(-190.0) - 170.0) < 1e-9, "{}", lon(-190.0));
+        assert!(f64::abs(lon(720.0 + 45.0) - 45.0) < 1e-9, "{}", lon(765.0));
+
+        // values in the range stay unchanged
+        assert_eq!(Point::builder(0).lat(0.0).lon(PI).build().unwrap().lon, PI);
+        assert_eq!(Point::builder(0).lat(0.0).lon(-0.5).build().unwrap().lon, -0.5);
+    }
+}
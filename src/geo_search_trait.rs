@@ -0,0 +1,28 @@
+use super::{geo_error::GeoError, geo_point::Point, relation::Relation};
+
+/// Common interface of data structures that solve the geosearch problem
+pub trait GeoSearch {
+    /// Find all points within the distance around the point
+    /// 
+    /// ## Arguments
+    /// 
+    /// * 'point' - Center of the search
+    /// * 'distance' - Search radius. Measure unit = km.
+    /// 
+    /// ## Returns
+    /// 
+    /// * 'relation' - Relation with 2 columns => (Node.id, Distance) or an error for invalid input
+    fn search_by_distance<R: Relation>(&self, point: &Point, distance: f64) -> Result<R, GeoError>;
+
+    /// Find all points inside the box between two corners
+    /// 
+    /// ## Arguments
+    /// 
+    /// * 'corner_west' - West corner of the box
+    /// * 'corner_east' - East corner of the box
+    /// 
+    /// ## Returns
+    /// 
+    /// * 'relation' - Relation with 1 column => (Node.id) or an error for invalid input
+    fn search_by_box<R: Relation>(&self, corner_west: &Point, corner_east: &Point) -> Result<R, GeoError>;
+}
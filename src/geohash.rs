@@ -0,0 +1,210 @@
+use std::f64::consts::PI;
+
+use crate::{geo_error::GeoError, geo_point::Point, search_box::SearchBox};
+
+pub struct GeohashHelper {
+    // this struct does not have any field
+    // static methods only
+}
+
+// alphabet of geohash characters, every character encodes 5 bits
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+// cells are found by comparing coordinates in degrees => rounding in radians can move a point on a border of a cell to the other side
+const BORDER_MARGIN: f64 = 1e-12;
+
+// (from, to) in degrees
+type Range = (f64, f64);
+
+/*
+Geohash https://en.wikipedia.org/wiki/Geohash
+Bits of longitude and latitude (in degrees) are interleaved, longitude first, and written in base32.
+Every hash is a cell, a longer hash is a smaller cell inside the cell of its prefix
+*/
+impl GeohashHelper {
+
+    // 12 characters = 60 bits => cells of a few centimeters, more bits are beyond the precision of f64 degrees
+    pub const MAX_PRECISION: usize = 12;
+
+    // hash of the cell of the given length that contains the point
+    pub fn encode(point: &Point, precision: usize) -> Result<String, GeoError> {
+        point.validate()?;
+        Self::validate_precision(precision)?;
+        return Ok(Self::encode_degrees(point.lat_degrees(), point.lon_degrees(), precision));
+    }
+
+    // center of the cell
+    pub fn decode(id: usize, hash: &str) -> Result<Point, GeoError> {
+        let ((lat_from, lat_to), (lon_from, lon_to)) = Self::cell(hash)?;
+        return Ok(Point::from_degrees(id, (lat_from + lat_to) / 2.0, (lon_from + lon_to) / 2.0));
+    }
+
+    // box of the cell in radians
+    pub fn search_box(hash: &str) -> Result<SearchBox, GeoError> {
+        let ((lat_from, lat_to), (lon_from, lon_to)) = Self::cell(hash)?;
+        return Ok(SearchBox {
+            lat_from: f64::max(lat_from.to_radians() - BORDER_MARGIN, -PI / 2.0),
+            lat_to: f64::min(lat_to.to_radians() + BORDER_MARGIN, PI / 2.0),
+            lon_from: f64::max(lon_from.to_radians() - BORDER_MARGIN, -PI),
+            lon_to: f64::min(lon_to.to_radians() + BORDER_MARGIN, PI)
+        });
+    }
+
+    // true if the point is inside the cell of the hash. The hash must be valid and lowercase
+    pub fn contains(hash: &str, point: &Point) -> bool {
+        return Self::encode_degrees(point.lat_degrees(), point.lon_degrees(), hash.len()) == hash;
+    }
+
+    /*
+    Cells of the same length around the cell: north, north-east, east, south-east, south, south-west, west, north-west.
+    Longitude wraps around the antimeridian, cells beyond a pole do not exist and are left out
+    */
+    pub fn neighbors(hash: &str) -> Result<Vec::<String>, GeoError> {
+
+        let ((lat_from, lat_to), (lon_from, lon_to)) = Self::cell(hash)?;
+        let (height, width) = (lat_to - lat_from, lon_to - lon_from);
+        let (lat, lon) = ((lat_from + lat_to) / 2.0, (lon_from + lon_to) / 2.0);
+
+        let directions = [(1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (-1.0, 1.0), (-1.0, 0.0), (-1.0, -1.0), (0.0, -1.0), (1.0, -1.0)];
+
+        let mut neighbors = Vec::with_capacity(directions.len());
+        for (d_lat, d_lon) in directions {
+            let neighbor_lat = lat + d_lat * height;
+            if !(-90.0..=90.0).contains(&neighbor_lat) {
+                continue;
+            }
+
+            let mut neighbor_lon = lon + d_lon * width;
+            if neighbor_lon > 180.0 {
+                neighbor_lon -= 360.0;
+            }
+            if neighbor_lon < -180.0 {
+                neighbor_lon += 360.0;
+            }
+
+            neighbors.push(Self::encode_degrees(neighbor_lat, neighbor_lon, hash.len()));
+        }
+
+        return Ok(neighbors);
+    }
+
+    // ranges of latitude and longitude of the cell in degrees. Characters are case-insensitive
+    fn cell(hash: &str) -> Result<(Range, Range), GeoError> {
+
+        Self::validate_precision(hash.len())?;
+
+        let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+        let mut is_lon = true;
+
+        for (position, character) in hash.bytes().enumerate() {
+            let value = BASE32.iter().position(|&symbol| symbol == character.to_ascii_lowercase())
+                .ok_or_else(|| GeoError::InvalidGeohash { hash: hash.to_string(), position })?;
+
+            for bit in (0..5).rev() {
+                let range: &mut Range = if is_lon { &mut lon_range } else { &mut lat_range };
+                let middle = (range.0 + range.1) / 2.0;
+                if value & (1 << bit) != 0 {
+                    range.0 = middle;
+                }
+                else {
+                    range.1 = middle;
+                }
+                is_lon = !is_lon;
+            }
+        }
+
+        return Ok((lat_range, lon_range));
+    }
+
+    fn encode_degrees(lat: f64, lon: f64, precision: usize) -> String {
+
+        let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+        let mut is_lon = true;
+
+        let mut hash = String::with_capacity(precision);
+        for _ in 0..precision {
+            let mut value = 0;
+            for _ in 0..5 {
+                let (range, coordinate): (&mut Range, f64) = if is_lon { (&mut lon_range, lon) } else { (&mut lat_range, lat) };
+                let middle = (range.0 + range.1) / 2.0;
+                value <<= 1;
+                if coordinate >= middle {
+                    value |= 1;
+                    range.0 = middle;
+                }
+                else {
+                    range.1 = middle;
+                }
+                is_lon = !is_lon;
+            }
+            hash.push(BASE32[value] as char);
+        }
+
+        return hash;
+    }
+
+    fn validate_precision(precision: usize) -> Result<(), GeoError> {
+        if precision == 0 || precision > Self::MAX_PRECISION {
+            return Err(GeoError::InvalidGeohashLength(precision));
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{geo_error::GeoError, geo_point::Point};
+    use super::GeohashHelper;
+
+    #[test]
+    fn encode_and_decode_known_hash() {
+        // example of https://en.wikipedia.org/wiki/Geohash
+        let point = Point::from_degrees(0, 57.64911, 10.40744);
+        assert_eq!(GeohashHelper::encode(&point, 11).unwrap(), "u4pruydqqvj");
+        assert_eq!(GeohashHelper::encode(&point, 3).unwrap(), "u4p");
+
+        // the center of the cell is within half of the cell (about 1.4e-6 degrees) from the point, case does not matter
+        for hash in ["u4pruydqqvj", "U4PRUYDQQVJ"] {
+            let center = GeohashHelper::decode(7, hash).unwrap();
+            assert_eq!(center.id, 7);
+            assert!(f64::abs(center.lat_degrees() - 57.64911) < 1e-5, "{:?}", center);
+            assert!(f64::abs(center.lon_degrees() - 10.40744) < 1e-5, "{:?}", center);
+            assert!(GeohashHelper::contains("u4pruydqqvj", &center));
+        }
+    }
+
+    #[test]
+    fn neighbors_wrap_around_antimeridian() {
+        // the cell touches the antimeridian from the west => eastern neighbors are at -180 degrees
+        let neighbors = GeohashHelper::neighbors("xbpbp").unwrap();
+        assert_eq!(neighbors, vec!["xbpbr", "80002", "80000", "2pbpb", "rzzzz", "rzzzy", "xbpbn", "xbpbq"]);
+    }
+
+    #[test]
+    fn cells_at_poles_have_no_neighbors_beyond_them() {
+        assert_eq!(GeohashHelper::neighbors("zzz").unwrap(), vec!["bpb", "bp8", "zzx", "zzw", "zzy"]);
+        assert_eq!(GeohashHelper::neighbors("pbp").unwrap(), vec!["pbr", "002", "000", "pbn", "pbq"]);
+
+        // both poles are inside the cells at the corners of the alphabet
+        assert_eq!(GeohashHelper::encode(&Point::from_degrees(0, 90.0, 180.0), 3).unwrap(), "zzz");
+        assert_eq!(GeohashHelper::encode(&Point::from_degrees(0, -90.0, -180.0), 3).unwrap(), "000");
+        assert!(GeohashHelper::contains("pbp", &Point::from_degrees(0, -90.0, 179.0)));
+    }
+
+    #[test]
+    fn invalid_characters_and_lengths_are_rejected() {
+        // a, i, l and o are not in the alphabet
+        for character in ["a", "i", "l", "o", "-"] {
+            let hash = format!("u4{}", character);
+            assert_eq!(GeohashHelper::decode(0, &hash).map(|_| ()), Err(GeoError::InvalidGeohash { hash: hash.clone(), position: 2 }));
+            assert_eq!(GeohashHelper::neighbors(&hash), Err(GeoError::InvalidGeohash { hash: hash.clone(), position: 2 }));
+        }
+
+        assert_eq!(GeohashHelper::decode(0, "").map(|_| ()), Err(GeoError::InvalidGeohashLength(0)));
+        assert_eq!(GeohashHelper::search_box("u4pruydqqvjjj").map(|_| ()), Err(GeoError::InvalidGeohashLength(13)));
+
+        let point = Point::from_degrees(0, 57.64911, 10.40744);
+        assert_eq!(GeohashHelper::encode(&point, 0), Err(GeoError::InvalidGeohashLength(0)));
+        assert_eq!(GeohashHelper::encode(&point, 13), Err(GeoError::InvalidGeohashLength(13)));
+    }
+}
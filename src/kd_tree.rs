@@ -1,108 +1,216 @@
-use crate::{geo_point::Point, sphere_helper::SphereHelper, search_box::SearchBox};
-use std::f64::consts::PI;
-use std::cmp::Ordering;
+use crate::{geo_point::{Locatable, Point}, search_box::SearchBox, polygon::Polygon};
+use crate::{distance_metric::{DistanceMetric, Haversine}, geo_error::GeoError, geo_search_trait::GeoSearch, relation::{Node, Relation}};
+use crate::binary_format::{self, ChecksumWriter, Header, NodeRecord, KIND_LEAF, KIND_NODE};
+use crate::tree_view::{self, TreeView};
+use rayon::prelude::*;
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+// subtrees with fewer points are built sequentially by build_parallel
+const PARALLEL_BUILD_SIZE: usize = 10_000;
+
+// build or build_parallel: (nodes, base, indices, points, n_stop) => index of the built node
+type BuildFn<T> = fn(&mut [KDTreeNode<T>], usize, &mut [usize], &[T], usize) -> usize;
 
 /*
 KD-Tree node = one node of a KD-Tree
 It can be a intermediate node without large amount of data
 Or it can be a leaf with data points
 */
-#[derive(Debug, Clone)]
-enum KDTreeNode {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum KDTreeNode<T> {
 
     Node {
         splitter: f64, // the value which was used to split points in two parts
         dimension: usize, // which dimension was used for split. 0 => latitude, 1 => lontitude
         left_child: usize, // index of the left child in array
         right_child: usize, // index of the right child in array
+        count: usize, // number of points in the subtree
     },
     Leaf {
-        points: Vec::<Point>
+        points: Vec::<T>
     }
 }
 
 
-//  tree structure
+/*
+Entry of a priority queue ordered by distance
+Is used for nearest neighbour search to keep nodes and found points ordered
+*/
 #[derive(Debug)]
-pub struct KDTree {
+struct Candidate<V> {
+    distance: f64,
+    value: V
+}
+
+impl<V> PartialEq for Candidate<V> {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl<V> Eq for Candidate<V> {}
+
+impl<V> PartialOrd for Candidate<V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl<V> Ord for Candidate<V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return self.distance.partial_cmp(&other.distance).unwrap_or(Ordering::Equal);
+    }
+}
+
+
+//  tree structure. Items are points or points with a payload (see Locatable), distances are measured by the metric M
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KDTree<T = Point, M = Haversine> {
     root: usize, // index of root in the array of nodes
-    nodes: Vec<KDTreeNode>, // array of all nodes to get rid of pointers
-    sphere_radius: f64 // radius of the sphere that describes points
+    nodes: Vec<KDTreeNode<T>>, // array of all nodes to get rid of pointers
+    metric: M, // way to measure distances between points
+    n_stop: usize, // maximal number of points in a leaf
+    parents: Vec<Option<usize>>, // index of the parent of every node, None for the root
+    leaves: HashMap<usize, usize> // id of every point => index of the leaf that contains it
 }
 
 /*
 kd-tree idead = https://en.wikipedia.org/wiki/K-d_tree
 here can be found algorithms that basically ensure fast search in kd-tree
 */
-impl KDTree {
+impl<T: Locatable + Clone, M: DistanceMetric> KDTree<T, M> {
 
-    // n_stop parameter to get rid of splitting dense areas => we don't want to create a tree very deep 
-    pub fn new(points: &Vec::<Point>, n_stop: usize, sphere_radius: f64) -> Self {
+    // the same as new, but the tree is built in the current thread => items do not have to be Send + Sync (e.g. payloads with Rc)
+    pub fn new_sequential(points: &[T], n_stop: usize, metric: M) -> Result<Self, GeoError> {
+        return Self::from_points(points, n_stop, metric, Self::build);
+    }
+
+    // check the input and build the tree with the given build function (sequential or parallel)
+    fn from_points(points: &[T], n_stop: usize, metric: M, build: BuildFn<T>) -> Result<Self, GeoError> {
+
+        // every split needs at least two points => leaves can not be empty
+        if n_stop == 0 {
+            return Err(GeoError::InvalidLeafSize(n_stop));
+        }
+        metric.validate()?;
+        let mut ids = HashSet::with_capacity(points.len());
+        for item in points {
+            item.point().validate()?;
+            if !ids.insert(item.point().id) {
+                return Err(GeoError::DuplicateId(item.point().id));
+            }
+        }
 
         // default initialization
-        // nodes = array of all tree nodes that will be constructed 
-        let mut nodes = Vec::new();
+        // nodes = array of all tree nodes. Its size is known in advance => subtrees can be built in parallel into their own parts of it
+        let mut nodes = Self::empty_nodes(Self::tree_size(points.len(), n_stop));
         let mut indices: Vec<usize> = (0..points.len()).collect();
 
         // build the tree
-        let idx = Self::build(&mut nodes, &mut indices, &points, n_stop);
-        return KDTree { 
+        let idx = build(&mut nodes, 0, &mut indices, points, n_stop);
+        let mut tree = KDTree { 
             root: idx,
-            nodes: nodes,
-            sphere_radius: sphere_radius
+            nodes,
+            metric,
+            n_stop,
+            parents: Vec::new(),
+            leaves: HashMap::with_capacity(points.len())
         };
+        tree.link_all();
+        return Ok(tree);
     }
 
-    fn build(nodes: &mut Vec<KDTreeNode>, indices: &mut Vec<usize>, points: &Vec::<Point>, n_stop: usize) -> usize {
+    // nodes are placed in post-order: left subtree, right subtree, node itself => the layout does not depend on the order of building
+    // base = index of the first given node in the whole array. Returns the index of the built node (the last one)
+    fn build(nodes: &mut [KDTreeNode<T>], base: usize, indices: &mut [usize], points: &[T], n_stop: usize) -> usize {
 
         // if number of points is small enough => then it's a leaf else build node !!!
         if indices.len() <= n_stop {
-            let idx = nodes.len() as usize;
-            nodes.push(KDTreeNode::Leaf { 
-                points: indices.iter().map(|&i| points[i].clone()).collect()
-            });
-            return idx;
+            return Self::build_leaf(nodes, base, indices, points);
         } 
-        
+
+        let (splitter, dimension, median_index) = Self::split_points(indices, points);
+
+        let count = indices.len();
+        let (left_indices, right_indices) = indices.split_at_mut(median_index + 1);
+        let left_size = Self::tree_size(left_indices.len(), n_stop);
+        let (left_nodes, other_nodes) = nodes.split_at_mut(left_size);
+        let (right_nodes, own_node) = other_nodes.split_at_mut(other_nodes.len() - 1);
+
+        // build childs and node itself
+        let left_child = Self::build(left_nodes, base, left_indices, points, n_stop);
+        let right_child = Self::build(right_nodes, base + left_size, right_indices, points, n_stop);
+        own_node[0] = KDTreeNode::Node { splitter, dimension, left_child, right_child, count };
+
+        return base + nodes.len() - 1;
+    }
+
+    fn build_leaf(nodes: &mut [KDTreeNode<T>], base: usize, indices: &[usize], points: &[T]) -> usize {
+        debug_assert_eq!(nodes.len(), 1);
+        nodes[0] = KDTreeNode::Leaf { 
+            points: indices.iter().map(|&i| points[i].clone()).collect()
+        };
+        return base;
+    }
+
+    // put the median in place and find the value that splits points in two parts. Returns (splitter, dimension, index of the median)
+    fn split_points(indices: &mut [usize], points: &[T]) -> (f64, usize, usize) {
+
         // identify dimension for split
         let dimension = Self::choose_dimension(points, indices);
-        indices.sort_by(|&a, &b| {
-            if dimension == 0 {
-                points[a].lat.partial_cmp(&points[b].lat).unwrap_or(Ordering::Equal)
-            } else {
-                points[a].lon.partial_cmp(&points[b].lon).unwrap_or(Ordering::Equal)
-            }
-        });
-        
+        let coordinate = |i: usize| if dimension == 0 { points[i].point().lat } else { points[i].point().lon };
+
         // get median point for better split. We do not want point on border.
+        // only the median has to be in place => linear-time selection instead of sorting
         let median_index = (indices.len() - 1) / 2;
+        indices.select_nth_unstable_by(median_index, |&a, &b| coordinate(a).partial_cmp(&coordinate(b)).unwrap_or(Ordering::Equal));
 
-        // identify value that splits points in two arrays
-        let split_value = if dimension == 0 { 
-            (points[indices[median_index]].lat + points[indices[median_index + 1]].lat) / 2.0
-        } 
-        else {
-            (points[indices[median_index]].lon + points[indices[median_index + 1]].lon) / 2.0
-        };
-        
-        // build childs and node itself
-        let node = KDTreeNode::Node {
-            splitter: split_value,
-            dimension: dimension,
-            left_child: Self::build(nodes, &mut indices[..median_index + 1].to_vec(), &points, n_stop),
-            right_child: Self::build(nodes, &mut indices[median_index + 1..].to_vec(), &points, n_stop),
+        // identify value that splits points in two arrays. The next point after the median is the smallest one on the right side
+        let median = coordinate(indices[median_index]);
+        let next = indices[median_index + 1..].iter().map(|&i| coordinate(i)).fold(f64::INFINITY, f64::min);
+
+        return ((median + next) / 2.0, dimension, median_index);
+    }
+
+    // number of nodes in a tree built from the given number of points
+    fn tree_size(count: usize, n_stop: usize) -> usize {
+        return Self::tree_sizes(count, n_stop).0;
+    }
+
+    // numbers of nodes in trees built from count and count + 1 points
+    // both halves of them have count / 2 or count / 2 + 1 points => one step per level of the tree
+    fn tree_sizes(count: usize, n_stop: usize) -> (usize, usize) {
+
+        if count < n_stop {
+            return (1, 1);
+        }
+
+        let half = count / 2;
+        let (half_size, next_size) = Self::tree_sizes(half, n_stop);
+        let size = |total: usize| {
+            if total <= n_stop {
+                return 1;
+            }
+            return [total - total / 2, total / 2].iter().map(|&part| if part == half { half_size } else { next_size }).sum::<usize>() + 1;
         };
-        
-        // add node to the vector
-        let idx = nodes.len() as usize;
-        nodes.push(node);
 
-        return idx;
+        return (size(count), size(count + 1));
     }
 
-    fn choose_dimension(points: &Vec::<Point>, indices: &Vec<usize>) -> usize {
-        let x_values: Vec<f64> = indices.iter().map(|&i| points[i].lat).collect();
-        let y_values: Vec<f64> = indices.iter().map(|&i| points[i].lon).collect();
+    // placeholders for nodes that are not built yet
+    fn empty_nodes(size: usize) -> Vec::<KDTreeNode<T>> {
+        return (0..size).map(|_| KDTreeNode::Leaf { points: Vec::new() }).collect();
+    }
+
+    fn choose_dimension(points: &[T], indices: &[usize]) -> usize {
+        let x_values: Vec<f64> = indices.iter().map(|&i| points[i].point().lat).collect();
+        let y_values: Vec<f64> = indices.iter().map(|&i| points[i].point().lon).collect();
 
         if Self::calculate_variance(&x_values) > Self::calculate_variance(&y_values) {
             return 0; // Choose x dimension
@@ -111,212 +219,1009 @@ impl KDTree {
         }
     }
 
-    fn calculate_variance(values: &Vec<f64>) -> f64 {
+    fn calculate_variance(values: &[f64]) -> f64 {
         let mean = values.iter().sum::<f64>() / values.len() as f64;
         values.iter().map(|&x| (x - mean).powi(2)).sum::<f64>() / values.len() as f64
     }
 
-    pub fn insert(&mut self, point: &Point) {
-        // Implementation of insertion goes here
-        // You would need to modify the vector and potentially update the tree structure
-        // based on the point's position
+    pub fn insert(&mut self, item: &T) -> Result<(), GeoError> {
+
+        let point = item.point();
+        point.validate()?;
+
+        // the map of leaves keeps one leaf per id => a second point with the id could never be removed
+        if self.leaves.contains_key(&point.id) {
+            return Err(GeoError::DuplicateId(point.id));
+        }
+
+        // go down to the leaf that covers the point. Points on the splitter belong to the left child
+        let mut node_idx = self.root;
+        while let KDTreeNode::Node { splitter, dimension, left_child, right_child, count } = &mut self.nodes[node_idx] {
+            *count += 1;
+            let value = if *dimension == 0 { point.lat } else { point.lon };
+            node_idx = if value <= *splitter { *left_child } else { *right_child };
+        }
+
+        let overflow = match &mut self.nodes[node_idx] {
+            KDTreeNode::Leaf { points } => {
+                points.push(item.clone());
+                points.len() > self.n_stop
+            },
+            KDTreeNode::Node { .. } => unreachable!("descent always stops at a leaf")
+        };
+        self.leaves.insert(point.id, node_idx);
+
+        // leaf is too large => split it in a subtree
+        if overflow {
+            self.split_leaf(node_idx);
+        }
+
+        return Ok(());
     }
 
-    pub fn search_by_distance(&self, point: &Point, distance: f64) -> Vec::<Point> {
+    // replace the leaf with a subtree built from its points. Root of the subtree takes place of the leaf in the array
+    fn split_leaf(&mut self, leaf_idx: usize) {
 
-        let (box_0, box_1) = SphereHelper::find_box(&point, distance, self.sphere_radius);
-        let initial_box = SearchBox {
-            lat_from: -PI / 2.0,
-            lat_to: PI / 2.0,
-            lon_from: -PI,
-            lon_to: PI
+        let points = match &mut self.nodes[leaf_idx] {
+            KDTreeNode::Leaf { points } => std::mem::take(points),
+            KDTreeNode::Node { .. } => return
         };
 
-        // collect candidates for results
-        let mut candidates = Vec::<Point>::new();
+        let mut indices: Vec<usize> = (0..points.len()).collect();
+        let base = self.nodes.len();
+        self.nodes.extend(Self::empty_nodes(Self::tree_size(points.len(), self.n_stop)));
+        let subtree_root = Self::build(&mut self.nodes[base..], base, &mut indices, &points, self.n_stop);
 
-        if let Some(target) = box_0 {
-            candidates.extend(self.search(self.root, &initial_box, &target))
+        // root is always the last built node => move it to the place of the leaf
+        debug_assert_eq!(subtree_root, self.nodes.len() - 1);
+        if let Some(node) = self.nodes.pop() {
+            self.nodes[leaf_idx] = node;
         }
 
-        if let Some(target) = box_1 {
-            candidates.extend(self.search(self.root, &initial_box, &target));
+        // new nodes and the moved root point to their children and points
+        self.parents.resize(self.nodes.len(), None);
+        for node_idx in (base..self.nodes.len()).chain([leaf_idx]) {
+            self.link(node_idx);
         }
+    }
 
-        // filter candidates and get the answers
-        let results = candidates.into_iter().filter(|candidate| {
-            SphereHelper::distance(point, candidate, self.sphere_radius) <= distance
-        }).collect();
+    // set parents and the map of leaves for the whole array
+    fn link_all(&mut self) {
+        self.parents = vec![None; self.nodes.len()];
+        self.leaves.clear();
+        for node_idx in 0..self.nodes.len() {
+            self.link(node_idx);
+        }
+    }
 
-        return results;
+    // make children of the node point to it as their parent, points of a leaf to the leaf
+    fn link(&mut self, node_idx: usize) {
+        match &self.nodes[node_idx] {
+            &KDTreeNode::Node { left_child, right_child, .. } => {
+                self.parents[left_child] = Some(node_idx);
+                self.parents[right_child] = Some(node_idx);
+            },
+            KDTreeNode::Leaf { points } => {
+                for item in points {
+                    self.leaves.insert(item.point().id, node_idx);
+                }
+            }
+        }
     }
 
-    fn search(&self, node_idx: usize, current_box: &SearchBox, target: &SearchBox) -> Vec::<Point> {
+    // remove the point with the given id. Returns the removed item if it was found
+    pub fn remove(&mut self, id: usize) -> Option::<T> {
 
-        if SearchBox::nested_box(&current_box, &target) {
-            return self.extract_all(node_idx);
+        // the leaf of the point is known => no search over the tree
+        let leaf_idx = *self.leaves.get(&id)?;
+        let removed = match &mut self.nodes[leaf_idx] {
+            KDTreeNode::Leaf { points } => {
+                let position = points.iter().position(|item| item.point().id == id)?;
+                points.swap_remove(position)
+            },
+            KDTreeNode::Node { .. } => return None
+        };
+        self.leaves.remove(&id);
+
+        // go up to the root: every subtree on the way has one point less and may be merged
+        let mut released = Vec::<usize>::new();
+        let mut parent = self.parents[leaf_idx];
+        while let Some(node_idx) = parent {
+            if let KDTreeNode::Node { count, .. } = &mut self.nodes[node_idx] {
+                *count -= 1;
+            }
+            self.merge_children(node_idx, &mut released);
+            parent = self.parents[node_idx];
         }
 
-        match &self.nodes[node_idx] {
-            KDTreeNode::Leaf { points } => {
-                return points.into_iter().filter(|&point| target.is_inside(&point)).map(|point| point.clone()).collect();
+        // nodes that were merged into their parents are not used anymore => delete them from the array
+        released.sort_unstable_by(|a, b| b.cmp(a));
+        for node_idx in released {
+            self.release_node(node_idx);
+        }
+
+        return Some(removed);
+    }
+
+    // move the point with the given id to new coordinates. Returns the old position of the point if it was found
+    pub fn update(&mut self, id: usize, lat: f64, lon: f64) -> Result<Option::<Point>, GeoError> {
+
+        // check new coordinates first => an invalid update does not lose the point
+        let new_point = Point { id, lat, lon };
+        new_point.validate()?;
+
+        let mut item = match self.remove(id) {
+            Some(item) => item,
+            None => return Ok(None)
+        };
+        let old_point = *item.point();
+        *item.point_mut() = new_point;
+        self.insert(&item)?;
+
+        return Ok(Some(old_point));
+    }
+
+    // merge children of the node if they became too small after a removal
+    fn merge_children(&mut self, node_idx: usize, released: &mut Vec<usize>) {
+
+        let (left_child, right_child) = match &self.nodes[node_idx] {
+            &KDTreeNode::Node { left_child, right_child, .. } => (left_child, right_child),
+            KDTreeNode::Leaf { .. } => return
+        };
+
+        // take children out of the array. Released slots stay as empty leaves without references to other nodes
+        let left = std::mem::replace(&mut self.nodes[left_child], KDTreeNode::Leaf { points: Vec::new() });
+        let right = std::mem::replace(&mut self.nodes[right_child], KDTreeNode::Leaf { points: Vec::new() });
+
+        self.nodes[node_idx] = match (left, right) {
+            // both leaves are underfull => the node becomes a single leaf
+            (KDTreeNode::Leaf { points: mut left_points }, KDTreeNode::Leaf { points: right_points }) if left_points.len() + right_points.len() <= self.n_stop / 2 => {
+                left_points.extend(right_points);
+                KDTreeNode::Leaf { points: left_points }
             },
-            &KDTreeNode::Node { splitter, dimension, left_child, right_child } => {
-
-                // search using latitude as split criteria in the node
-                let mut result = Vec::<Point>::new();
-
-                if dimension == 0 {
-                    if target.lat_from <= splitter && splitter < target.lat_to {
-                        // here we have to split our search in two parts and search in both directions
-                        
-                        // left child
-                        result.extend(self.search(left_child, 
-                            &SearchBox {
-                                lat_from: current_box.lat_from,
-                                lat_to: splitter,
-                                lon_from: current_box.lon_from,
-                                lon_to: current_box.lon_to
-                            }, 
-                            &SearchBox {
-                                lat_from: target.lat_from,
-                                lat_to: splitter,
-                                lon_from: target.lon_from,
-                                lon_to: target.lon_to
-                            }
-                        ));
-
-                        // right child
-                        result.extend(self.search(right_child, 
-                            &SearchBox {
-                                lat_from: splitter,
-                                lat_to: current_box.lat_to,
-                                lon_from: current_box.lon_from,
-                                lon_to: current_box.lon_to
-                            },
-                            &SearchBox {
-                                lat_from: splitter,
-                                lat_to: target.lat_to,
-                                lon_from: target.lon_from,
-                                lon_to: target.lon_to
-                            }
-                        ));
+            // one child is empty => the other one takes place of the node
+            (KDTreeNode::Leaf { points }, right) if points.is_empty() => right,
+            (left, KDTreeNode::Leaf { points }) if points.is_empty() => left,
+            // nothing to merge => put children back
+            (left, right) => {
+                self.nodes[left_child] = left;
+                self.nodes[right_child] = right;
+                return;
+            }
+        };
 
-                    }
-                    else if target.lat_from < splitter && target.lat_to <= splitter {
-                        // search just in the left subtree
-                        result.extend(self.search(left_child, 
-                            &SearchBox {
-                                lat_from: current_box.lat_from,
-                                lat_to: splitter,
-                                lon_from: current_box.lon_from,
-                                lon_to: current_box.lon_to
-                            }, 
-                            target
-                        ));
+        // the node has new children or points
+        self.link(node_idx);
+        released.extend([left_child, right_child]);
+    }
+
+    // delete the node from the array. The last node takes its place => update the references to the last node
+    fn release_node(&mut self, node_idx: usize) {
+
+        let last_idx = self.nodes.len() - 1;
+        self.nodes.swap_remove(node_idx);
+        self.parents.swap_remove(node_idx);
+
+        if node_idx == last_idx {
+            return;
+        }
+
+        // the parent of the moved node refers to its new place, its children and points too
+        match self.parents[node_idx] {
+            Some(parent) => {
+                if let KDTreeNode::Node { left_child, right_child, .. } = &mut self.nodes[parent] {
+                    if *left_child == last_idx {
+                        *left_child = node_idx;
                     }
                     else {
-                        // search just in the right subtree
-                        result.extend(self.search(right_child, 
-                            &SearchBox {
-                                lat_from: splitter,
-                                lat_to: current_box.lat_to,
-                                lon_from: current_box.lon_from,
-                                lon_to: current_box.lon_to
-                            },
-                            target
-                        ));
+                        *right_child = node_idx;
                     }
                 }
-                // search using lontitude as split criteria in the node
-                else {
-                    
-                    if target.lon_from <= splitter && splitter < target.lon_to {
-                        // here we have to split our search in two parts and search in both directions
-                        
-                        // left child
-                        result.extend(self.search(left_child, 
-                            &SearchBox {
-                                lat_from: current_box.lat_from,
-                                lat_to: current_box.lat_to,
-                                lon_from: current_box.lon_from,
-                                lon_to: splitter
-                            }, 
-                            &SearchBox {
-                                lat_from: target.lat_from,
-                                lat_to: target.lat_to,
-                                lon_from: target.lon_from,
-                                lon_to: splitter
-                            }
-                        ));
-
-                        // right child
-                        result.extend(self.search(right_child, 
-                            &SearchBox {
-                                lat_from: current_box.lat_from,
-                                lat_to: current_box.lat_to,
-                                lon_from: splitter,
-                                lon_to: current_box.lon_to
-                            },
-                            &SearchBox {
-                                lat_from: target.lat_from,
-                                lat_to: target.lat_to,
-                                lon_from: splitter,
-                                lon_to: target.lon_to
-                            }
-                        ));
-                    }
-                    else if target.lon_from < splitter && target.lon_to <= splitter {
-                        // search just in the left subtree
-                        result.extend(self.search(left_child, 
-                            &SearchBox {
-                                lat_from: current_box.lat_from,
-                                lat_to: current_box.lat_to,
-                                lon_from: current_box.lon_from,
-                                lon_to: splitter
-                            }, 
-                            target
-                        ));
+            },
+            None => self.root = node_idx
+        }
+        self.link(node_idx);
+    }
+
+    pub fn search_by_distance(&self, point: &Point, distance: f64) -> Result<Vec::<T>, GeoError> {
+        return Ok(self.search_by_distance_iter(point, distance)?.map(|(candidate, _)| candidate.clone()).collect());
+    }
+
+    // lazy version of search_by_distance_with_distances (unsorted). Leaves are read only when the next point is requested
+    pub fn search_by_distance_iter(&self, point: &Point, distance: f64) -> Result<impl Iterator<Item = (&T, f64)> + '_, GeoError> {
+        return tree_view::search_by_distance_iter(self, point, distance);
+    }
+
+    // the same as search_by_distance, but each point comes together with its distance to the center (like wikibase:distance)
+    // if sorted => the closest points come first
+    pub fn search_by_distance_with_distances(&self, point: &Point, distance: f64, sorted: bool) -> Result<Vec::<(T, f64)>, GeoError> {
+        let results = tree_view::search_by_distance_with_distances(self, point, distance, sorted)?;
+        return Ok(results.into_iter().map(|(candidate, candidate_distance)| (candidate.clone(), candidate_distance)).collect());
+    }
+
+    // all points inside the box between two corners. Corners are defined the same way as in Wikidata (cornerWest, cornerEast)
+    pub fn search_by_box(&self, corner_west: &Point, corner_east: &Point) -> Result<Vec::<T>, GeoError> {
+        return Ok(self.search_by_box_iter(corner_west, corner_east)?.cloned().collect());
+    }
+
+    // lazy version of search_by_box
+    pub fn search_by_box_iter(&self, corner_west: &Point, corner_east: &Point) -> Result<impl Iterator<Item = &T> + '_, GeoError> {
+        return tree_view::search_by_box(self, corner_west, corner_east);
+    }
+
+    // all points inside the geohash cell of the prefix
+    pub fn search_by_geohash(&self, prefix: &str) -> Result<Vec::<T>, GeoError> {
+        return Ok(tree_view::search_by_geohash(self, prefix)?.cloned().collect());
+    }
+
+    // all points inside the polygon. Bounding SearchBoxes of the polygon prune the tree, candidates are checked exactly
+    pub fn search_by_polygon(&self, polygon: &Polygon) -> Vec::<T> {
+        let candidates = tree_view::search(self, polygon.bounding_boxes());
+        return candidates.filter(|candidate| polygon.contains(candidate.point())).cloned().collect();
+    }
+
+    // number of points within the distance around the point. Subtrees that lie inside the circle are counted in O(1)
+    pub fn count_by_distance(&self, point: &Point, distance: f64) -> Result<usize, GeoError> {
+        return tree_view::count_by_distance(self, point, distance);
+    }
+
+    // number of points inside the box between two corners. Subtrees that lie inside the box are counted in O(1)
+    pub fn count_by_box(&self, corner_west: &Point, corner_east: &Point) -> Result<usize, GeoError> {
+        return tree_view::count_by_box(self, corner_west, corner_east);
+    }
+
+    // all pairs (id in this tree, id in the other tree, distance) within the distance from each other. Both trees must be built for the same metric
+    pub fn join_by_distance<U: Locatable>(&self, other: &KDTree<U, M>, distance: f64) -> Result<Vec::<(usize, usize, f64)>, GeoError> {
+        let mut results = Vec::new();
+        tree_view::join_by_distance(self, other, distance, &mut |first: &T, second: &U, pair_distance| {
+            results.push((first.point().id, second.point().id, pair_distance));
+        })?;
+        return Ok(results);
+    }
+
+    // all pairs (id, id, distance) of points of this tree within the distance from each other. Every unordered pair is reported once
+    pub fn pairs_within(&self, distance: f64) -> Result<Vec::<(usize, usize, f64)>, GeoError> {
+        let mut results = Vec::new();
+        tree_view::pairs_within(self, distance, &mut |first: &T, second: &T, pair_distance| {
+            results.push((first.point().id, second.point().id, pair_distance));
+        })?;
+        return Ok(results);
+    }
+
+    // k closest points ordered by distance. Best-first search over nodes ordered by distance to their SearchBox
+    pub fn nearest(&self, point: &Point, k: usize) -> Result<Vec::<T>, GeoError> {
+
+        point.validate()?;
+
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+
+        let initial_box = SearchBox::whole_sphere();
+
+        // queue = nodes to visit, the closest one first. results = k best points, the farthest one on top
+        let mut queue = BinaryHeap::new();
+        let mut results = BinaryHeap::<Candidate<T>>::with_capacity(k + 1);
+
+        queue.push(Reverse(Candidate { distance: 0.0, value: (self.root, initial_box) }));
+
+        while let Some(Reverse(Candidate { distance, value: (node_idx, current_box) })) = queue.pop() {
+
+            // all other nodes are farther away than the k-th found point => stop
+            if results.len() == k && results.peek().is_some_and(|worst| worst.distance < distance) {
+                break;
+            }
+
+            match &self.nodes[node_idx] {
+                KDTreeNode::Leaf { points } => {
+                    for candidate in points {
+                        let candidate_distance = self.metric.distance(point, candidate.point());
+
+                        if results.len() < k {
+                            results.push(Candidate { distance: candidate_distance, value: candidate.clone() });
+                        }
+                        else if results.peek().is_some_and(|worst| candidate_distance < worst.distance) {
+                            results.pop();
+                            results.push(Candidate { distance: candidate_distance, value: candidate.clone() });
+                        }
                     }
-                    else {
-                        // search just in the right subtree
-                        result.extend(self.search(right_child, 
-                            &SearchBox {
-                                lat_from: current_box.lat_from,
-                                lat_to: current_box.lat_to,
-                                lon_from: splitter,
-                                lon_to: current_box.lon_to
-                            },
-                            target
-                        ));
+                },
+                &KDTreeNode::Node { splitter, dimension, left_child, right_child, .. } => {
+                    let (left_box, right_box) = current_box.split(splitter, dimension);
 
+                    for (child, child_box) in [(left_child, left_box), (right_child, right_box)] {
+                        let child_distance = self.metric.distance_to_box(point, &child_box);
+
+                        // skip subtrees that can not contain anything better
+                        if results.len() < k || results.peek().is_some_and(|worst| child_distance <= worst.distance) {
+                            queue.push(Reverse(Candidate { distance: child_distance, value: (child, child_box) }));
+                        }
                     }
                 }
+            }
+        }
 
-                return result;
+        return Ok(results.into_sorted_vec().into_iter().map(|candidate| candidate.value).collect());
+    }
+}
+
+// parallel building and batch queries share items between threads
+impl<T: Locatable + Clone + Send + Sync, M: DistanceMetric> KDTree<T, M> {
+
+    // n_stop parameter to get rid of splitting dense areas => we don't want to create a tree very deep 
+    // metric = Haversine { radius }, Vincenty, Euclidean for planar data etc. (see distance_metric)
+    // large subtrees are built in parallel, see new_sequential for items that can not be shared between threads
+    // ids of points must be unique: points are removed and updated by id
+    pub fn new(points: &[T], n_stop: usize, metric: M) -> Result<Self, GeoError> {
+        return Self::from_points(points, n_stop, metric, Self::build_parallel);
+    }
+
+    // the same as build, but large subtrees are built in parallel
+    fn build_parallel(nodes: &mut [KDTreeNode<T>], base: usize, indices: &mut [usize], points: &[T], n_stop: usize) -> usize {
+
+        // subtrees with fewer points are built in the current thread => no overhead of scheduling for small tasks
+        if indices.len() < PARALLEL_BUILD_SIZE {
+            return Self::build(nodes, base, indices, points, n_stop);
+        }
+
+        let (splitter, dimension, median_index) = Self::split_points(indices, points);
+
+        let count = indices.len();
+        let (left_indices, right_indices) = indices.split_at_mut(median_index + 1);
+        let left_size = Self::tree_size(left_indices.len(), n_stop);
+        let (left_nodes, other_nodes) = nodes.split_at_mut(left_size);
+        let (right_nodes, own_node) = other_nodes.split_at_mut(other_nodes.len() - 1);
+
+        let (left_child, right_child) = rayon::join(
+            || Self::build_parallel(left_nodes, base, left_indices, points, n_stop),
+            || Self::build_parallel(right_nodes, base + left_size, right_indices, points, n_stop)
+        );
+        own_node[0] = KDTreeNode::Node { splitter, dimension, left_child, right_child, count };
+
+        return base + nodes.len() - 1;
+    }
+
+    // many radius searches at once (point, distance), executed in parallel. Results are in the order of the queries,
+    // an invalid query gets its own error and does not stop the others
+    pub fn search_by_distance_batch(&self, queries: &[(Point, f64)]) -> Vec::<Result<Vec::<T>, GeoError>> {
+        return queries.par_iter().map(|(point, distance)| self.search_by_distance(point, *distance)).collect();
+    }
+
+    // many nearest neighbour searches at once (point, k), executed in parallel. Results are in the order of the queries,
+    // an invalid query gets its own error and does not stop the others
+    pub fn nearest_batch(&self, queries: &[(Point, usize)]) -> Vec::<Result<Vec::<T>, GeoError>> {
+        return queries.par_iter().map(|(point, k)| self.nearest(point, *k)).collect();
+    }
+}
+
+// the binary format stores plain points, payloads are not saved
+impl<M: DistanceMetric> KDTree<Point, M> {
+
+    // save the tree to a file in the binary format (see binary_format)
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), GeoError> {
+
+        let mut writer = ChecksumWriter::new(BufWriter::new(File::create(path)?));
+
+        let point_count = self.nodes.iter().map(|node| match node {
+            KDTreeNode::Leaf { points } => points.len(),
+            KDTreeNode::Node { .. } => 0
+        }).sum();
+
+        Header {
+            metric: M::CODE,
+            radius: self.metric.radius(),
+            n_stop: self.n_stop,
+            root: self.root,
+            node_count: self.nodes.len(),
+            point_count
+        }.write_to(&mut writer)?;
+
+        // points of leaves are stored one after another in the order of nodes
+        let mut point_offset = 0;
+        for node in self.nodes.iter() {
+            let record = match node {
+                &KDTreeNode::Node { splitter, dimension, left_child, right_child, count } => {
+                    NodeRecord { kind: KIND_NODE, dimension: dimension as u32, splitter, first: left_child, second: right_child, count }
+                },
+                KDTreeNode::Leaf { points } => {
+                    point_offset += points.len();
+                    NodeRecord { kind: KIND_LEAF, dimension: 0, splitter: 0.0, first: point_offset - points.len(), second: points.len(), count: points.len() }
+                }
+            };
+            record.write_to(&mut writer)?;
+        }
+
+        for node in self.nodes.iter() {
+            if let KDTreeNode::Leaf { points } = node {
+                for point in points {
+                    binary_format::write_point(&mut writer, point)?;
+                }
+            }
+        }
+
+        writer.finish()?;
+        return Ok(());
+    }
+
+    // load a tree saved by save. The tree must have been built for the same metric
+    pub fn load<P: AsRef<Path>>(path: P, metric: M) -> Result<Self, GeoError> {
+        return Self::from_bytes(&std::fs::read(path)?, metric);
+    }
+
+    fn from_bytes(bytes: &[u8], metric: M) -> Result<Self, GeoError> {
+
+        let header = Header::read_from(bytes)?;
+        binary_format::verify_checksum(bytes)?;
+
+        binary_format::verify_metric(&header, &metric)?;
+
+        binary_format::verify_structure(bytes, &header)?;
+        binary_format::verify_points(bytes, &header)?;
+
+        let nodes = (0..header.node_count).map(|idx| {
+            let record = NodeRecord::read_from(bytes, header.node_offset(idx));
+
+            if record.kind == KIND_NODE {
+                return KDTreeNode::Node {
+                    splitter: record.splitter,
+                    dimension: record.dimension as usize,
+                    left_child: record.first,
+                    right_child: record.second,
+                    count: record.count
+                };
             }
+
+            return KDTreeNode::Leaf {
+                points: (record.first..record.first + record.second).map(|point_idx| {
+                    binary_format::read_point(bytes, header.point_offset(point_idx))
+                }).collect()
+            };
+        }).collect();
+
+        let mut tree = KDTree {
+            root: header.root,
+            nodes,
+            metric,
+            n_stop: header.n_stop,
+            parents: Vec::new(),
+            leaves: HashMap::with_capacity(header.point_count)
+        };
+        tree.link_all();
+
+        // the file was not written by save => points with the same id
+        if tree.leaves.len() != header.point_count {
+            return Err(GeoError::InvalidFormat("ids of points are not unique".to_string()));
         }
+
+        return Ok(tree);
     }
+}
 
-    fn extract_all(&self, node_idx: usize) -> Vec<Point> {
+impl<T: Locatable, M: DistanceMetric> TreeView for KDTree<T, M> {
+    type Value = T;
+    type Item<'a> = &'a T where T: 'a, M: 'a;
+    type Metric = M;
 
+    fn root(&self) -> usize {
+        return self.root;
+    }
+
+    fn metric(&self) -> M {
+        return self.metric;
+    }
+
+    fn split(&self, node_idx: usize) -> Option<(f64, usize, usize, usize)> {
+        match self.nodes[node_idx] {
+            KDTreeNode::Node { splitter, dimension, left_child, right_child, .. } => Some((splitter, dimension, left_child, right_child)),
+            KDTreeNode::Leaf { .. } => None
+        }
+    }
+
+    fn leaf_len(&self, node_idx: usize) -> usize {
         match &self.nodes[node_idx] {
-            &KDTreeNode::Node { splitter: _, dimension: _, left_child, right_child } => {
+            KDTreeNode::Leaf { points } => points.len(),
+            KDTreeNode::Node { .. } => 0
+        }
+    }
 
-                let mut result = Vec::<Point>::new();
+    fn subtree_len(&self, node_idx: usize) -> usize {
+        match &self.nodes[node_idx] {
+            KDTreeNode::Leaf { points } => points.len(),
+            KDTreeNode::Node { count, .. } => *count
+        }
+    }
 
-                // collect everything from left child
-                result.extend(self.extract_all(left_child));
+    fn leaf_item(&self, node_idx: usize, position: usize) -> &T {
+        match &self.nodes[node_idx] {
+            KDTreeNode::Leaf { points } => &points[position],
+            KDTreeNode::Node { .. } => panic!("node {} is not a leaf", node_idx)
+        }
+    }
+}
 
-                // collect everything from right child
-                result.extend(self.extract_all(right_child));
+impl<T: Locatable + Clone, M: DistanceMetric> GeoSearch for KDTree<T, M> {
+    fn search_by_distance<R: Relation>(&self, point: &Point, distance: f64) -> Result<R, GeoError> {
+        return self.search_by_distance_iter(point, distance)?.map(|(candidate, distance_to_candidate)| {
+            // Return a relation with 2 columns => (Node.id, Distance)
+            let distance_to_candidate = distance_to_candidate.to_f32().ok_or(GeoError::ValueOverflow(distance_to_candidate))?;
+            return Ok(vec![Node::Id(candidate.point().id), Node::NumberFloat(distance_to_candidate)]);
+        }).collect();
+    }
 
-                return result;
-            },
-            KDTreeNode::Leaf { points } => {
-                return points.iter().map(|&point| point.clone()).collect();
+    fn search_by_box<R: Relation>(&self, corner_west: &Point, corner_east: &Point) -> Result<R, GeoError> {
+        return Ok(self.search_by_box_iter(corner_west, corner_east)?.map(|candidate| {
+            return vec![Node::Id(candidate.point().id)];
+        }).collect());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use rand::{rngs::StdRng, Rng};
+
+    use crate::{binary_format, distance_metric::{self, DistanceMetric, Equirectangular, Euclidean, Haversine}, geo_error::GeoError, geo_point::Point, sphere_helper::SphereHelper};
+    use crate::{geo_search_trait::GeoSearch, relation::Node};
+    use crate::test_helper::{centers, clustered_points, seeded, TempFile, RADIUS};
+    use super::{KDTree, KDTreeNode};
+
+    // (id, distance) of the k closest points, found by checking every point
+    fn brute_force_nearest(points: &[Point], center: &Point, k: usize) -> Vec<(usize, f64)> {
+        let metric = Haversine { radius: RADIUS };
+        let mut distances: Vec<(usize, f64)> = points.iter().map(|point| (point.id, metric.distance(center, point))).collect();
+        distances.sort_by(|a, b| a.1.total_cmp(&b.1));
+        distances.truncate(k);
+        return distances;
+    }
+
+    #[test]
+    fn nearest_matches_brute_force_near_poles_and_antimeridian() {
+        let mut rng = seeded(1);
+        let points = clustered_points(&mut rng, 20_000);
+        let tree = KDTree::new(&points, 16, Haversine { radius: RADIUS }).unwrap();
+        let metric = Haversine { radius: RADIUS };
+
+        for center in centers() {
+            for k in [1, 7, 100] {
+                let expected = brute_force_nearest(&points, &center, k);
+                let found = tree.nearest(&center, k).unwrap();
+
+                assert_eq!(found.len(), k);
+                for (point, (id, distance)) in found.iter().zip(expected.iter()) {
+                    assert!(f64::abs(metric.distance(&center, point) - distance) < 1e-9, "{:?}: {:?} instead of {}", center, point, id);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_returns_all_points_if_k_is_larger() {
+        let points = vec![Point { id: 0, lat: 0.1, lon: PI - 0.01 }, Point { id: 1, lat: 0.1, lon: -PI + 0.01 }, Point { id: 2, lat: -1.5, lon: 0.0 }];
+        let tree = KDTree::new(&points, 1, Haversine { radius: RADIUS }).unwrap();
+
+        let found: Vec<usize> = tree.nearest(&Point { id: 0, lat: 0.1, lon: PI - 0.002 }, 10).unwrap().iter().map(|point| point.id).collect();
+        assert_eq!(found, vec![0, 1, 2]);
+        assert!(tree.nearest(&Point { id: 0, lat: 0.0, lon: 0.0 }, 0).unwrap().is_empty());
+    }
+
+    // parents, sizes of subtrees and the map of leaves agree with the nodes reachable from the root
+    fn assert_consistent(tree: &KDTree) {
+        assert_eq!(tree.parents.len(), tree.nodes.len());
+        assert_eq!(tree.parents[tree.root], None);
+
+        let mut stack = vec![tree.root];
+        let mut visited = 0;
+        let mut point_count = 0;
+        while let Some(node_idx) = stack.pop() {
+            visited += 1;
+            match &tree.nodes[node_idx] {
+                &KDTreeNode::Node { left_child, right_child, count, .. } => {
+                    assert_eq!(tree.parents[left_child], Some(node_idx));
+                    assert_eq!(tree.parents[right_child], Some(node_idx));
+                    assert_eq!(count, points_under(tree, left_child) + points_under(tree, right_child));
+                    stack.extend([left_child, right_child]);
+                },
+                KDTreeNode::Leaf { points } => {
+                    for point in points {
+                        assert_eq!(tree.leaves.get(&point.id), Some(&node_idx));
+                    }
+                    point_count += points.len();
+                }
+            }
+        }
+
+        // no released nodes are left in the array
+        assert_eq!(visited, tree.nodes.len());
+        assert_eq!(point_count, tree.leaves.len());
+    }
+
+    // number of points under the node, counted over its leaves
+    fn points_under(tree: &KDTree, node_idx: usize) -> usize {
+        match &tree.nodes[node_idx] {
+            &KDTreeNode::Node { left_child, right_child, .. } => points_under(tree, left_child) + points_under(tree, right_child),
+            KDTreeNode::Leaf { points } => points.len()
+        }
+    }
+
+    // compare radius searches and counts with a check of every point
+    fn assert_queries_match(tree: &KDTree, expected: &HashMap<usize, Point>, rng: &mut StdRng) {
+        let metric = Haversine { radius: RADIUS };
+        let all = clustered_points(rng, 8);
+
+        for center in centers().iter().chain(all.iter()) {
+            for distance in [10.0, 300.0, 3000.0] {
+                let mut found: Vec<usize> = tree.search_by_distance(center, distance).unwrap().iter().map(|point| point.id).collect();
+                found.sort_unstable();
+                let mut within: Vec<usize> = expected.values().filter(|point| metric.distance(center, point) <= distance).map(|point| point.id).collect();
+                within.sort_unstable();
+
+                assert_eq!(found, within);
+                assert_eq!(tree.count_by_distance(center, distance).unwrap(), within.len());
+            }
+        }
+
+        for corners in all.chunks(2) {
+            let (west, east) = (Point { lat: f64::min(corners[0].lat, corners[1].lat), ..corners[0] }, Point { lat: f64::max(corners[0].lat, corners[1].lat), ..corners[1] });
+            let boxes = SphereHelper::construct_searchbox(&west, &east).unwrap();
+            let inside = expected.values().filter(|point| [boxes.0, boxes.1].iter().flatten().any(|target| target.is_inside(point))).count();
+
+            assert_eq!(tree.count_by_box(&west, &east).unwrap(), inside);
+        }
+    }
+
+    #[test]
+    fn random_inserts_removes_and_updates_match_brute_force() {
+        let mut rng = seeded(3);
+        let initial = clustered_points(&mut rng, 2_000);
+        let mut tree = KDTree::new(&initial, 8, Haversine { radius: RADIUS }).unwrap();
+        let mut expected: HashMap<usize, Point> = initial.iter().map(|point| (point.id, *point)).collect();
+        let mut next_id = initial.len();
+
+        for step in 0..4_000 {
+            let existing = *expected.keys().nth(rng.gen_range(0..expected.len())).unwrap();
+            match rng.gen_range(0..4) {
+                0 => {
+                    let point = Point { id: next_id, ..clustered_points(&mut rng, 1)[0] };
+                    tree.insert(&point).unwrap();
+                    expected.insert(next_id, point);
+                    next_id += 1;
+                },
+                1 => {
+                    assert_eq!(tree.remove(existing).map(|point| point.id), Some(existing));
+                    expected.remove(&existing);
+                },
+                2 => {
+                    let target = clustered_points(&mut rng, 1)[0];
+                    let old = tree.update(existing, target.lat, target.lon).unwrap().unwrap();
+                    assert_eq!((old.lat, old.lon), (expected[&existing].lat, expected[&existing].lon));
+                    expected.insert(existing, Point { id: existing, ..target });
+                },
+                _ => {
+                    assert!(tree.remove(next_id + 1).is_none());
+                    assert!(tree.update(next_id + 1, 0.0, 0.0).unwrap().is_none());
+                }
+            }
+
+            if step % 250 == 0 {
+                assert_consistent(&tree);
+                assert_queries_match(&tree, &expected, &mut rng);
+            }
+        }
+
+        // remove everything => the tree is a single empty leaf
+        let ids: Vec<usize> = expected.keys().copied().collect();
+        for id in ids {
+            assert!(tree.remove(id).is_some());
+            expected.remove(&id);
+        }
+        assert_consistent(&tree);
+        assert_eq!(tree.nodes.len(), 1);
+        assert_queries_match(&tree, &expected, &mut rng);
+    }
+
+    // save a tree of random points, change the file and load it again
+    fn load_changed(name: &str, change: impl FnOnce(&mut Vec<u8>), metric: Haversine) -> Result<KDTree, GeoError> {
+        let mut rng = seeded(11);
+        let tree = KDTree::new(&clustered_points(&mut rng, 1_000), 8, Haversine { radius: RADIUS }).unwrap();
+
+        let file = TempFile::new(name);
+        tree.save(file.path()).unwrap();
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        change(&mut bytes);
+        std::fs::write(file.path(), bytes).unwrap();
+
+        return KDTree::load(file.path(), metric);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut rng = seeded(7);
+        let points = clustered_points(&mut rng, 5_000);
+        let mut tree = KDTree::new(&points, 8, Haversine { radius: RADIUS }).unwrap();
+
+        // a modified tree has released and split nodes
+        for id in 0..500 {
+            tree.remove(id);
+        }
+        for point in points.iter().take(300) {
+            tree.insert(&Point { id: point.id + points.len(), ..*point }).unwrap();
+        }
+
+        let file = TempFile::new("round_trip");
+        tree.save(file.path()).unwrap();
+        let loaded = KDTree::load(file.path(), Haversine { radius: RADIUS }).unwrap();
+
+        assert_eq!(loaded.root, tree.root);
+        assert_eq!(loaded.n_stop, tree.n_stop);
+        assert_eq!(loaded.leaves, tree.leaves);
+        assert_consistent(&loaded);
+
+        for center in centers() {
+            let ids = |tree: &KDTree| {
+                let mut ids: Vec<usize> = tree.search_by_distance(&center, 2000.0).unwrap().iter().map(|point| point.id).collect();
+                ids.sort_unstable();
+                return ids;
+            };
+            assert_eq!(ids(&loaded), ids(&tree));
+            assert_eq!(loaded.count_by_distance(&center, 2000.0).unwrap(), ids(&tree).len());
+
+            let nearest = |tree: &KDTree| tree.nearest(&center, 5).unwrap().iter().map(|point| (point.id, point.lat, point.lon)).collect::<Vec<_>>();
+            assert_eq!(nearest(&loaded), nearest(&tree));
+        }
+    }
+
+    #[test]
+    fn load_rejects_corrupted_byte() {
+        let result = load_changed("corrupted", |bytes| {
+            let idx = bytes.len() - 10;
+            bytes[idx] ^= 0x01;
+        }, Haversine { radius: RADIUS });
+
+        assert!(matches!(result, Err(GeoError::ChecksumMismatch { .. })), "{:?}", result.err());
+    }
+
+    #[test]
+    fn load_rejects_other_version() {
+        let result = load_changed("version", |bytes| {
+            bytes[8..12].copy_from_slice(&(binary_format::VERSION + 1).to_le_bytes());
+        }, Haversine { radius: RADIUS });
+
+        assert_eq!(result.err(), Some(GeoError::UnsupportedVersion { found: binary_format::VERSION + 1, expected: binary_format::VERSION }));
+    }
+
+    #[test]
+    fn load_rejects_other_radius() {
+        let result = load_changed("radius", |_| {}, Haversine { radius: 6000.0 });
+
+        assert_eq!(result.err(), Some(GeoError::MetricMismatch {
+            found: distance_metric::describe_metric(Haversine::CODE, RADIUS),
+            expected: distance_metric::describe_metric(Haversine::CODE, 6000.0)
+        }));
+    }
+
+    #[test]
+    fn sequential_and_parallel_builds_are_equal() {
+        let mut rng = seeded(17);
+        let points = clustered_points(&mut rng, 3 * super::PARALLEL_BUILD_SIZE);
+
+        let parallel = KDTree::new(&points, 8, Haversine { radius: RADIUS }).unwrap();
+        let sequential = KDTree::new_sequential(&points, 8, Haversine { radius: RADIUS }).unwrap();
+
+        assert_eq!(format!("{:?}", parallel.nodes), format!("{:?}", sequential.nodes));
+        assert_eq!(parallel.root, sequential.root);
+        assert_consistent(&sequential);
+    }
+
+    #[test]
+    fn payload_without_send_and_sync() {
+        // payloads share a handle that can not be sent to other threads
+        let graph = Rc::new(vec!["a", "b", "c"]);
+        let mut rng = seeded(19);
+        let items: Vec<(Point, Rc<Vec<&str>>)> = clustered_points(&mut rng, 1_000).into_iter().map(|point| (point, Rc::clone(&graph))).collect();
+
+        let mut tree = KDTree::new_sequential(&items, 8, Haversine { radius: RADIUS }).unwrap();
+        let extra = Point { id: 5_000, lat: 0.01, lon: 0.02 };
+        tree.insert(&(extra, Rc::clone(&graph))).unwrap();
+        assert!(tree.remove(0).is_some());
+
+        let center = Point { id: 0, lat: 0.0, lon: 0.0 };
+        let found = tree.search_by_distance(&center, 500.0).unwrap();
+        assert!(found.iter().any(|(point, payload)| point.id == 5_000 && payload[1] == "b"));
+        assert_eq!(tree.count_by_distance(&center, 500.0).unwrap(), found.len());
+        assert_eq!(tree.nearest(&extra, 1).unwrap()[0].0.id, 5_000);
+
+        let relation: Vec<Vec<Node>> = GeoSearch::search_by_distance(&tree, &center, 500.0).unwrap();
+        assert_eq!(relation.len(), found.len());
+    }
+
+    // (first id, second id) of all pairs within the distance, found by checking every pair
+    fn nested_loop_pairs<M: DistanceMetric>(metric: M, first: &[Point], second: &[Point], distance: f64) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for first_point in first {
+            for second_point in second {
+                if metric.distance(first_point, second_point) <= distance {
+                    pairs.push((first_point.id, second_point.id));
+                }
+            }
+        }
+        pairs.sort();
+        return pairs;
+    }
+
+    fn assert_join_matches_nested_loop<M: DistanceMetric>(metric: M, distances: &[f64]) {
+        let mut rng = seeded(23);
+        let first = clustered_points(&mut rng, 800);
+
+        // the second tree has other ids and repeats some points of the first one exactly
+        let mut second: Vec<Point> = clustered_points(&mut rng, 800).into_iter().map(|point| Point { id: point.id + 10_000, ..point }).collect();
+        for (position, point) in first.iter().step_by(40).enumerate() {
+            second[position] = Point { id: second[position].id, ..*point };
+        }
+
+        let first_tree = KDTree::new(&first, 8, metric).unwrap();
+        let second_tree = KDTree::new(&second, 3, metric).unwrap();
+
+        for &distance in distances {
+            let mut found: Vec<(usize, usize)> = first_tree.join_by_distance(&second_tree, distance).unwrap().into_iter().map(|(first_id, second_id, _)| (first_id, second_id)).collect();
+            found.sort();
+            assert_eq!(found, nested_loop_pairs(metric, &first, &second, distance), "{:?} at distance {}", metric, distance);
+        }
+    }
+
+    #[test]
+    fn join_matches_nested_loop() {
+        assert_join_matches_nested_loop(Haversine { radius: RADIUS }, &[0.0, 10.0, 300.0, 3_000.0]);
+        assert_join_matches_nested_loop(Equirectangular { radius: RADIUS }, &[0.0, 10.0, 300.0, 3_000.0]);
+        assert_join_matches_nested_loop(Euclidean, &[0.0, 0.002, 0.05, 0.5]);
+    }
+
+    #[test]
+    fn pairs_within_matches_nested_loop_after_changes() {
+        let mut rng = seeded(29);
+        let metric = Haversine { radius: RADIUS };
+        let mut points = clustered_points(&mut rng, 1_000);
+        let mut tree = KDTree::new(&points, 4, metric).unwrap();
+
+        // split leaves are appended to the nodes and released nodes are swapped => nodes are no longer in post-order
+        for id in 1_000..1_200 {
+            let point = if id < 1_100 {
+                Point { id, lat: rng.gen_range(-1.5..=1.5), lon: rng.gen_range(-PI..=PI) }
+            } else {
+                Point { id, ..points[id - 1_100] }
+            };
+            tree.insert(&point).unwrap();
+            points.push(point);
+        }
+        for id in (0..1_000).step_by(7) {
+            assert!(tree.remove(id).is_some());
+        }
+        points.retain(|point| point.id >= 1_000 || point.id % 7 != 0);
+
+        for distance in [0.0, 20.0, 300.0, 3_000.0] {
+            let mut found: Vec<(usize, usize)> = tree.pairs_within(distance).unwrap().into_iter().map(|(first_id, second_id, _)| (usize::min(first_id, second_id), usize::max(first_id, second_id))).collect();
+            found.sort();
+            let expected: Vec<(usize, usize)> = nested_loop_pairs(metric, &points, &points, distance).into_iter().filter(|(first_id, second_id)| first_id < second_id).collect();
+            assert_eq!(found, expected, "distance {}", distance);
+        }
+    }
+
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let metric = Haversine { radius: RADIUS };
+        let twice = [Point { id: 1, lat: 0.1, lon: 0.2 }, Point { id: 2, lat: 0.3, lon: 0.4 }, Point { id: 1, lat: 0.5, lon: 0.6 }];
+        assert_eq!(KDTree::new(&twice, 1, metric).err(), Some(GeoError::DuplicateId(1)));
+        assert_eq!(KDTree::new_sequential(&twice, 1, metric).err(), Some(GeoError::DuplicateId(1)));
+
+        let mut tree = KDTree::new(&twice[..2], 1, metric).unwrap();
+        assert_eq!(tree.insert(&twice[2]), Err(GeoError::DuplicateId(1)));
+        assert_consistent(&tree);
+
+        // the tree is not changed => the only point with the id is moved and removed
+        let center = Point { id: 0, lat: 0.0, lon: 0.0 };
+        assert_eq!(tree.count_by_distance(&center, 20_000.0).unwrap(), 2);
+        assert_eq!(tree.update(1, 0.5, 0.6).unwrap().map(|point| (point.lat, point.lon)), Some((0.1, 0.2)));
+        assert_eq!(tree.remove(1).map(|point| (point.lat, point.lon)), Some((0.5, 0.6)));
+        assert!(tree.remove(1).is_none());
+        assert_eq!(tree.search_by_distance(&center, 20_000.0).unwrap().iter().map(|point| point.id).collect::<Vec<usize>>(), vec![2]);
+
+        // the id is free again
+        tree.insert(&twice[2]).unwrap();
+        assert_eq!(tree.count_by_distance(&center, 20_000.0).unwrap(), 2);
+    }
+
+    #[test]
+    fn batches_match_single_queries_in_order() {
+        let mut rng = seeded(61);
+        let tree = KDTree::new(&clustered_points(&mut rng, 5_000), 8, Haversine { radius: RADIUS }).unwrap();
+        let ids = |result: Result<Vec<Point>, GeoError>| result.map(|points| points.iter().map(|point| point.id).collect::<Vec<usize>>());
+
+        // invalid queries in the middle get their own errors
+        let mut distance_queries: Vec<(Point, f64)> = centers().into_iter().map(|center| (center, rng.gen_range(0.0..3_000.0))).collect();
+        distance_queries.insert(3, (Point { id: 0, lat: 2.0, lon: 0.0 }, 100.0));
+        distance_queries.insert(5, (Point { id: 0, lat: 0.0, lon: 0.0 }, -1.0));
+
+        let batch = tree.search_by_distance_batch(&distance_queries);
+        assert_eq!(batch.len(), distance_queries.len());
+        assert_eq!(batch.iter().map(|result| result.is_ok()).collect::<Vec<bool>>(), vec![true, true, true, false, true, false, true, true, true]);
+        for ((point, distance), result) in distance_queries.iter().zip(batch) {
+            assert_eq!(ids(result), ids(tree.search_by_distance(point, *distance)));
+        }
+
+        let mut nearest_queries: Vec<(Point, usize)> = centers().into_iter().map(|center| (center, rng.gen_range(0..200))).collect();
+        nearest_queries.insert(2, (Point { id: 0, lat: 0.0, lon: 4.0 }, 10));
+
+        let batch = tree.nearest_batch(&nearest_queries);
+        assert_eq!(batch.len(), nearest_queries.len());
+        assert!(batch[2].is_err() && batch.iter().filter(|result| result.is_ok()).count() == centers().len());
+        for ((point, k), result) in nearest_queries.iter().zip(batch) {
+            assert_eq!(ids(result), ids(tree.nearest(point, *k)));
+        }
+    }
+
+    #[test]
+    fn distances_of_radius_search_match_brute_force() {
+        let metric = Haversine { radius: RADIUS };
+        let points = clustered_points(&mut seeded(67), 5_000);
+        let tree = KDTree::new(&points, 8, metric).unwrap();
+
+        for center in centers() {
+            for distance in [0.0, 10.0, 300.0, 3_000.0, 25_000.0] {
+                let mut expected: Vec<(usize, f64)> = points.iter().map(|point| (point.id, metric.distance(&center, point))).filter(|&(_, d)| d <= distance).collect();
+                expected.sort_by_key(|&(id, _)| id);
+
+                for sorted in [false, true] {
+                    let found: Vec<(usize, f64)> = tree.search_by_distance_with_distances(&center, distance, sorted).unwrap().iter().map(|(point, d)| (point.id, *d)).collect();
+                    if sorted {
+                        assert!(found.windows(2).all(|pair| pair[0].1 <= pair[1].1), "not ascending around {:?} within {}", center, distance);
+                    }
+
+                    let mut found = found;
+                    found.sort_by_key(|&(id, _)| id);
+                    assert_eq!(found, expected, "around {:?} within {}, sorted: {}", center, distance, sorted);
+                }
             }
         }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn box_search_matches_brute_force() {
+        let mut rng = seeded(71);
+        let points = clustered_points(&mut rng, 5_000);
+        let tree = KDTree::new(&points, 8, Haversine { radius: RADIUS }).unwrap();
+        let corner = |lat: f64, lon: f64| Point { id: 0, lat, lon };
+
+        // (west, east): a plain box, boxes over the antimeridian (west > east), corners on the poles, the whole sphere
+        let mut boxes = vec![
+            (corner(-0.5, 0.2), corner(0.7, 1.1)),
+            (corner(-0.8, 3.0), corner(0.9, -3.0)),
+            (corner(0.0, PI - 0.01), corner(1.0, -PI)),
+            (corner(PI / 2.0, -1.0), corner(PI / 2.0 - 0.03, 2.0)),
+            (corner(-PI / 2.0, 2.5), corner(-1.0, -2.5)),
+            (corner(-PI / 2.0, -PI), corner(PI / 2.0, PI)),
+            (corner(PI / 2.0, 0.0), corner(PI / 2.0, 0.0))
+        ];
+        boxes.extend((0..50).map(|_| (corner(rng.gen_range(-PI / 2.0..=PI / 2.0), rng.gen_range(-PI..=PI)), corner(rng.gen_range(-PI / 2.0..=PI / 2.0), rng.gen_range(-PI..=PI)))));
+
+        for (west, east) in boxes {
+            let (lat_from, lat_to) = (f64::min(west.lat, east.lat), f64::max(west.lat, east.lat));
+            let mut expected: Vec<usize> = points.iter().filter(|point| {
+                let in_lon = if west.lon <= east.lon { west.lon <= point.lon && point.lon <= east.lon } else { west.lon <= point.lon || point.lon <= east.lon };
+                return lat_from <= point.lat && point.lat <= lat_to && in_lon;
+            }).map(|point| point.id).collect();
+            expected.sort_unstable();
+
+            let mut found: Vec<usize> = tree.search_by_box(&west, &east).unwrap().iter().map(|point| point.id).collect();
+            found.sort_unstable();
+            assert_eq!(found, expected, "box from {:?} to {:?}", west, east);
+            assert_eq!(tree.count_by_box(&west, &east).unwrap(), expected.len(), "box from {:?} to {:?}", west, east);
+        }
+
+        assert!(tree.search_by_box(&corner(1.6, 0.0), &corner(0.0, 1.0)).is_err());
+    }
+
+}
@@ -1,31 +1,54 @@
+#![allow(clippy::needless_return)]
+
 pub mod sphere_helper;
+pub mod ellipsoid_helper;
+pub mod distance_metric;
+pub mod geohash;
+pub mod wkt;
+pub mod geo_json;
+pub mod csv_loader;
 pub mod search_box;
 pub mod kd_tree;
+pub mod binary_format;
+pub mod tree_view;
+pub mod mapped_kd_tree;
 pub mod geo_point;
+pub mod geo_error;
+pub mod polygon;
+pub mod relation;
+pub mod geo_search_trait;
+pub mod geo_container;
+#[cfg(test)]
+mod test_helper;
 
 use std::f64::consts::PI;
 use rand::distributions::{Distribution, Uniform};
 use std::time::Instant;
 use geo_point::Point;
 use kd_tree::KDTree;
+use distance_metric::Haversine;
+use geo_error::GeoError;
+use csv_loader::CsvLoader;
 
-fn main() {
-
-    // generate points
-    let amount = 10_000 as usize;
+fn main() -> Result<(), GeoError> {
 
     let lat_generator = Uniform::new(-PI / 2.0 + 0.2, PI / 2.0 - 0.2);
     let lon_generator = Uniform::new(-PI + 0.2, PI - 0.2);
     let mut rng = rand::thread_rng();
 
-    let points: Vec::<Point> = (0..amount).into_iter().map(|idx| {
-        Point{id: idx, lat: lat_generator.sample(&mut rng), lon: lon_generator.sample(&mut rng)}
-    }).collect();
+    // load points from a CSV file (id,lat,lon in degrees) if it is given, otherwise generate them
+    let points: Vec::<Point> = match std::env::args().nth(1) {
+        Some(path) => CsvLoader::new().load(path)?,
+        None => (0..10_000_usize).map(|idx| {
+            Point{id: idx, lat: lat_generator.sample(&mut rng), lon: lon_generator.sample(&mut rng)}
+        }).collect()
+    };
+    let amount = points.len();
 
     // create a KDTree
     let radius = 1.0;
-    let n_stop = 300 as usize;
-    let kdtree = KDTree::new(&points, n_stop, radius);
+    let n_stop = 300_usize;
+    let kdtree = KDTree::new(&points, n_stop, Haversine { radius })?;
 
 
     // check the speed !!!
@@ -44,22 +67,24 @@ fn main() {
 
         // search points in three
         let start_tree = Instant::now();
-        let mut results = kdtree.search_by_distance(&target, distance);
+        let mut results = kdtree.search_by_distance(&target, distance)?;
         let duration = start_tree.elapsed().as_secs_f64();
 
         tree_res.push(duration);
 
-        results.sort_by(|&p1, &p2| p1.id.cmp(&p2.id));
+        results.sort_by_key(|&p1| p1.id);
 
         // simple search
         let start_search = Instant::now();
-        let simple_search: Vec::<Point> = points.clone().into_iter().filter(|&point| {
+        let mut simple_search: Vec::<Point> = points.clone().into_iter().filter(|&point| {
             sphere_helper::SphereHelper::distance(&target, &point, radius) <= distance
         }).collect();
         let duration = start_search.elapsed().as_secs_f64();
 
         simple_res.push(duration);
 
+        simple_search.sort_by_key(|&p1| p1.id);
+
         std::iter::zip(results, simple_search).for_each(|(left, right)| {
             if left.id != right.id {
                 println!("{:?}, {:?}, {:?}, {:?}", target, distance, left, right);
@@ -70,4 +95,5 @@ fn main() {
     println!("Average time for tree = {:?}", tree_res.iter().sum::<f64>() / tree_res.len() as f64);
     println!("Average time for simple search = {:?}", simple_res.iter().sum::<f64>() / tree_res.len() as f64);
 
+    return Ok(());
 }
\ No newline at end of file
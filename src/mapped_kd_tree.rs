@@ -0,0 +1,181 @@
+use std::fs::File;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::binary_format::{self, Header, NodeRecord, KIND_NODE};
+use crate::tree_view::{self, TreeView};
+use crate::{distance_metric::{DistanceMetric, Haversine}, geo_error::GeoError, geo_point::Point};
+
+/*
+KD-Tree saved by KDTree::save and mapped into memory.
+Nodes and points are read in place from the file => opening does not copy the tree into the heap,
+the operating system loads only the pages that are touched by queries.
+open reads the header and the nodes only, open_verified also checks the checksum and every point
+*/
+#[derive(Debug)]
+pub struct MappedKDTree<M = Haversine> {
+    mmap: Mmap, // content of the file
+    header: Header, // sizes and parameters of the tree
+    metric: M // way to measure distances between points
+}
+
+impl<M: DistanceMetric> MappedKDTree<M> {
+
+    // map the file and check its header and nodes. The tree must have been built for the same metric
+    // pages of points are not touched => fast restart, but corrupted points are not detected (see open_verified)
+    pub fn open<P: AsRef<Path>>(path: P, metric: M) -> Result<Self, GeoError> {
+
+        let file = File::open(path)?;
+
+        // SAFETY: the file must not be modified while it is mapped. Saved trees are written once and only read afterwards
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let header = Header::read_from(&mmap)?;
+        binary_format::verify_metric(&header, &metric)?;
+        binary_format::verify_structure(&mmap, &header)?;
+
+        return Ok(MappedKDTree { mmap, header, metric });
+    }
+
+    // the same as open, but the checksum and all points are checked too => reads the whole file once
+    pub fn open_verified<P: AsRef<Path>>(path: P, metric: M) -> Result<Self, GeoError> {
+
+        let tree = Self::open(path, metric)?;
+        binary_format::verify_checksum(&tree.mmap)?;
+        binary_format::verify_points(&tree.mmap, &tree.header)?;
+
+        return Ok(tree);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.header.point_count;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.header.point_count == 0;
+    }
+
+    pub fn search_by_distance(&self, point: &Point, distance: f64) -> Result<Vec::<Point>, GeoError> {
+        let results = tree_view::search_by_distance_with_distances(self, point, distance, false)?;
+        return Ok(results.into_iter().map(|(candidate, _)| candidate).collect());
+    }
+
+    // the same as search_by_distance, but each point comes together with its distance to the center
+    pub fn search_by_distance_with_distances(&self, point: &Point, distance: f64, sorted: bool) -> Result<Vec::<(Point, f64)>, GeoError> {
+        return tree_view::search_by_distance_with_distances(self, point, distance, sorted);
+    }
+
+    // all points inside the box between two corners (cornerWest, cornerEast like in Wikidata)
+    pub fn search_by_box(&self, corner_west: &Point, corner_east: &Point) -> Result<Vec::<Point>, GeoError> {
+        return Ok(tree_view::search_by_box(self, corner_west, corner_east)?.collect());
+    }
+
+    // all points inside the geohash cell of the prefix
+    pub fn search_by_geohash(&self, prefix: &str) -> Result<Vec::<Point>, GeoError> {
+        return Ok(tree_view::search_by_geohash(self, prefix)?.collect());
+    }
+
+    // number of points within the distance around the point
+    pub fn count_by_distance(&self, point: &Point, distance: f64) -> Result<usize, GeoError> {
+        return tree_view::count_by_distance(self, point, distance);
+    }
+
+    // number of points inside the box between two corners
+    pub fn count_by_box(&self, corner_west: &Point, corner_east: &Point) -> Result<usize, GeoError> {
+        return tree_view::count_by_box(self, corner_west, corner_east);
+    }
+
+    fn record(&self, node_idx: usize) -> NodeRecord {
+        return NodeRecord::read_from(&self.mmap, self.header.node_offset(node_idx));
+    }
+}
+
+impl<M: DistanceMetric> TreeView for MappedKDTree<M> {
+    type Value = Point;
+    type Item<'a> = Point where M: 'a;
+    type Metric = M;
+
+    fn root(&self) -> usize {
+        return self.header.root;
+    }
+
+    fn metric(&self) -> M {
+        return self.metric;
+    }
+
+    fn split(&self, node_idx: usize) -> Option<(f64, usize, usize, usize)> {
+        let record = self.record(node_idx);
+        if record.kind == KIND_NODE {
+            return Some((record.splitter, record.dimension as usize, record.first, record.second));
+        }
+        return None;
+    }
+
+    fn leaf_len(&self, node_idx: usize) -> usize {
+        return self.record(node_idx).second;
+    }
+
+    fn subtree_len(&self, node_idx: usize) -> usize {
+        return self.record(node_idx).count;
+    }
+
+    fn leaf_item(&self, node_idx: usize, position: usize) -> Point {
+        let first = self.record(node_idx).first;
+        return binary_format::read_point(&self.mmap, self.header.point_offset(first + position));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use crate::{distance_metric::Haversine, geo_error::GeoError, geo_point::Point, kd_tree::KDTree};
+    use crate::test_helper::{seeded, uniform_points, TempFile, RADIUS};
+    use super::MappedKDTree;
+
+    // save a tree of random points, return it together with the file
+    fn saved_tree(name: &str) -> (KDTree, TempFile) {
+        let tree = KDTree::new(&uniform_points(&mut seeded(13), 5_000), 8, Haversine { radius: RADIUS }).unwrap();
+
+        let file = TempFile::new(&format!("mapped_{}", name));
+        tree.save(file.path()).unwrap();
+        return (tree, file);
+    }
+
+    #[test]
+    fn mapped_tree_answers_like_tree() {
+        let (tree, file) = saved_tree("answers");
+        let mapped = MappedKDTree::open(file.path(), Haversine { radius: RADIUS }).unwrap();
+
+        assert_eq!(mapped.len(), 5_000);
+        for (lat, lon) in [(0.0, 0.0), (PI / 2.0, 0.0), (0.3, PI - 1e-3), (-1.2, -2.0)] {
+            let center = Point { id: 0, lat, lon };
+            for distance in [100.0, 1000.0, 5000.0] {
+                let ids = |mut points: Vec<Point>| {
+                    points.sort_by_key(|point| point.id);
+                    return points.iter().map(|point| point.id).collect::<Vec<usize>>();
+                };
+                let expected = ids(tree.search_by_distance(&center, distance).unwrap());
+
+                assert_eq!(ids(mapped.search_by_distance(&center, distance).unwrap()), expected);
+                assert_eq!(mapped.count_by_distance(&center, distance).unwrap(), expected.len());
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_point_is_found_by_open_verified_only() {
+        let (_, file) = saved_tree("corrupted");
+
+        // the last byte of the last point => no node is touched
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        let idx = bytes.len() - 5;
+        bytes[idx] ^= 0x01;
+        std::fs::write(file.path(), bytes).unwrap();
+
+        assert!(MappedKDTree::open(file.path(), Haversine { radius: RADIUS }).is_ok());
+        let result = MappedKDTree::open_verified(file.path(), Haversine { radius: RADIUS });
+        assert!(matches!(result, Err(GeoError::ChecksumMismatch { .. })), "{:?}", result.err());
+    }
+}
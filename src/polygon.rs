@@ -0,0 +1,340 @@
+use std::f64::consts::PI;
+
+use crate::{geo_error::GeoError, geo_point::Point, search_box::SearchBox, sphere_helper::SphereHelper, wkt::Geometry};
+
+/*
+Closed ring of a polygon. Edges are great-circle arcs between consecutive vertices (the short way)
+If the ring goes around a pole, the pole is stored => the ring contains it
+*/
+#[derive(Debug, Clone)]
+struct Ring {
+    vertices: Vec<Point>,
+    pole: Option<f64> // latitude of the enclosed pole
+}
+
+/*
+Polygon on the sphere with optional holes
+A point is inside if it is inside of the exterior ring and outside of every hole
+*/
+#[derive(Debug, Clone)]
+pub struct Polygon {
+    exterior: Ring,
+    holes: Vec<Ring>
+}
+
+impl Polygon {
+
+    // rings may be closed (last vertex = first vertex) or open
+    pub fn new(exterior: Vec<Point>, holes: Vec<Vec<Point>>) -> Result<Self, GeoError> {
+        return Ok(Polygon {
+            exterior: Ring::new(exterior)?,
+            holes: holes.into_iter().map(Ring::new).collect::<Result<Vec<Ring>, GeoError>>()?
+        });
+    }
+
+    // polygon of a WKT literal "POLYGON((lon lat, ...), ...)" in degrees (see wkt)
+    pub fn from_wkt(text: &str) -> Result<Self, GeoError> {
+        match Geometry::from_wkt(text)? {
+            Geometry::Polygon(polygon) => Ok(polygon),
+            _ => Err(GeoError::InvalidWkt { position: 0, message: "expected POLYGON".to_string() })
+        }
+    }
+
+    pub fn to_wkt(&self) -> String {
+        return Geometry::Polygon(self.clone()).to_wkt();
+    }
+
+    pub fn exterior(&self) -> &[Point] {
+        return &self.exterior.vertices;
+    }
+
+    pub fn holes(&self) -> impl Iterator<Item = &[Point]> {
+        return self.holes.iter().map(|hole| hole.vertices.as_slice());
+    }
+
+    // exact spherical point-in-polygon test
+    pub fn contains(&self, point: &Point) -> bool {
+        return self.exterior.contains(point) && !self.holes.iter().any(|hole| hole.contains(point));
+    }
+
+    // SearchBoxes that cover the polygon. Like in find_box the second one is used if the polygon crosses the antimeridian
+    pub fn bounding_boxes(&self) -> (Option::<SearchBox>, Option::<SearchBox>) {
+
+        let vertices = &self.exterior.vertices;
+
+        // great-circle edges can go further to the pole than their vertices
+        let mut lat_from = PI / 2.0;
+        let mut lat_to = -PI / 2.0;
+        for (start, end) in Ring::edges(vertices) {
+            let (edge_from, edge_to) = Self::edge_lat_range(start, end);
+            lat_from = f64::min(lat_from, edge_from);
+            lat_to = f64::max(lat_to, edge_to);
+        }
+
+        // the polygon goes around the pole => look around the pole
+        match self.exterior.pole {
+            Some(pole) if pole > 0.0 => {
+                return (Some(SearchBox { lat_from, lat_to: PI / 2.0, lon_from: -PI, lon_to: PI }), None);
+            },
+            Some(_) => {
+                return (Some(SearchBox { lat_from: -PI / 2.0, lat_to, lon_from: -PI, lon_to: PI }), None);
+            },
+            None => {}
+        }
+
+        // walk along the ring without jumps over the antimeridian to find the longitude range
+        let mut lon = vertices[0].lon;
+        let mut lon_from = lon;
+        let mut lon_to = lon;
+        for (start, end) in Ring::edges(vertices) {
+            lon += Self::lon_difference(start.lon, end.lon);
+            lon_from = f64::min(lon_from, lon);
+            lon_to = f64::max(lon_to, lon);
+        }
+
+        if lon_to - lon_from >= 2.0 * PI {
+            return (Some(SearchBox { lat_from, lat_to, lon_from: -PI, lon_to: PI }), None);
+        }
+
+        // if the polygon goes over the right border => look near the left border, because it's a sphere
+        if lon_to > PI {
+            return (
+                Some(SearchBox { lat_from, lat_to, lon_from, lon_to: PI }),
+                Some(SearchBox { lat_from, lat_to, lon_from: -PI, lon_to: lon_to - 2.0 * PI })
+            );
+        }
+
+        // if the polygon goes over the left border => look near the right border, because it's a sphere
+        if lon_from < -PI {
+            return (
+                Some(SearchBox { lat_from, lat_to, lon_from: -PI, lon_to }),
+                Some(SearchBox { lat_from, lat_to, lon_from: lon_from + 2.0 * PI, lon_to: PI })
+            );
+        }
+
+        return (Some(SearchBox { lat_from, lat_to, lon_from, lon_to }), None);
+    }
+
+    // latitude range of the great-circle arc between two points
+    fn edge_lat_range(start: &Point, end: &Point) -> (f64, f64) {
+
+        let mut lat_from = f64::min(start.lat, end.lat);
+        let mut lat_to = f64::max(start.lat, end.lat);
+
+        let (a, b) = (Self::to_vector(start), Self::to_vector(end));
+        let normal = Self::cross(&a, &b);
+        let norm = Self::dot(&normal, &normal).sqrt();
+        if norm < 1e-15 {
+            return (lat_from, lat_to);
+        }
+        let normal = normal.map(|value| value / norm);
+
+        // the most northern point of the great circle = projection of the north pole on the plane of the circle
+        let top = [-normal[2] * normal[0], -normal[2] * normal[1], 1.0 - normal[2] * normal[2]];
+        let top_norm = Self::dot(&top, &top).sqrt();
+        if top_norm < 1e-15 {
+            return (lat_from, lat_to);
+        }
+        let top = top.map(|value| value / top_norm);
+        let bottom = top.map(|value| -value);
+
+        // extreme points matter only if they lie on the arc
+        for extreme in [top, bottom] {
+            if Self::dot(&Self::cross(&a, &extreme), &normal) >= 0.0 && Self::dot(&Self::cross(&extreme, &b), &normal) >= 0.0 {
+                let lat = f64::asin(f64::clamp(extreme[2], -1.0, 1.0));
+                lat_from = f64::min(lat_from, lat);
+                lat_to = f64::max(lat_to, lat);
+            }
+        }
+
+        return (lat_from, lat_to);
+    }
+
+    // difference of longitudes the short way, in [-PI, PI]
+    fn lon_difference(lon_from: f64, lon_to: f64) -> f64 {
+        return SphereHelper::normalize_lon(lon_to - lon_from);
+    }
+
+    fn to_vector(point: &Point) -> [f64; 3] {
+        return [
+            f64::cos(point.lat) * f64::cos(point.lon),
+            f64::cos(point.lat) * f64::sin(point.lon),
+            f64::sin(point.lat)
+        ];
+    }
+
+    fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+        return [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0]
+        ];
+    }
+
+    fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+        return a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+    }
+}
+
+impl Ring {
+
+    fn new(mut vertices: Vec<Point>) -> Result<Self, GeoError> {
+
+        for vertex in vertices.iter() {
+            vertex.validate()?;
+        }
+
+        // closed ring => drop the repeated vertex
+        if vertices.len() > 1 {
+            let (first, last) = (vertices[0], vertices[vertices.len() - 1]);
+            if first.lat == last.lat && first.lon == last.lon {
+                vertices.pop();
+            }
+        }
+
+        if vertices.len() < 3 {
+            return Err(GeoError::InvalidRing { vertices: vertices.len() });
+        }
+
+        // ring goes around a pole if longitude makes a full turn along it. It encloses the pole of its hemisphere
+        let winding: f64 = Self::edges(&vertices).map(|(start, end)| Polygon::lon_difference(start.lon, end.lon)).sum();
+        let pole = if f64::abs(winding) > PI {
+            let mean_lat = vertices.iter().map(|vertex| vertex.lat).sum::<f64>() / vertices.len() as f64;
+            Some(if mean_lat >= 0.0 { PI / 2.0 } else { -PI / 2.0 })
+        } else {
+            None
+        };
+
+        return Ok(Ring { vertices, pole });
+    }
+
+    // pairs of consecutive vertices including the closing edge
+    fn edges(vertices: &[Point]) -> impl Iterator<Item = (&Point, &Point)> {
+        return vertices.iter().zip(vertices.iter().cycle().skip(1));
+    }
+
+    // count crossings of the meridian arc from the point to the north pole https://en.wikipedia.org/wiki/Point_in_polygon
+    fn contains(&self, point: &Point) -> bool {
+
+        let meridian_normal = [-f64::sin(point.lon), f64::cos(point.lon), 0.0];
+        let meridian_direction = [f64::cos(point.lon), f64::sin(point.lon), 0.0];
+
+        let mut inside = false;
+
+        for (start, end) in Self::edges(&self.vertices) {
+
+            // position of the vertices relative to the meridian of the point
+            let d_start = Polygon::lon_difference(point.lon, start.lon);
+            let d_end = Polygon::lon_difference(point.lon, end.lon);
+
+            // the edge does not cross the meridian, or crosses the opposite one
+            if (d_start > 0.0) == (d_end > 0.0) || f64::abs(d_end - d_start) >= PI {
+                continue;
+            }
+
+            // crossing of the edge and the meridian plane, taken on the side of the point
+            let crossing = Polygon::cross(&Polygon::cross(&Polygon::to_vector(start), &Polygon::to_vector(end)), &meridian_normal);
+            let horizontal = Polygon::dot(&crossing, &meridian_direction);
+            let crossing_lat = if horizontal >= 0.0 {
+                f64::atan2(crossing[2], horizontal)
+            } else {
+                f64::atan2(-crossing[2], -horizontal)
+            };
+
+            if crossing_lat > point.lat {
+                inside = !inside;
+            }
+        }
+
+        // the ray goes to the north pole => if it is inside, the answer is inverted
+        if self.pole.is_some_and(|pole| pole > 0.0) {
+            inside = !inside;
+        }
+
+        return inside;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use crate::{distance_metric::Haversine, geo_point::Point, kd_tree::KDTree};
+    use crate::test_helper::{point_in, seeded, RADIUS};
+    use super::Polygon;
+
+    // vertices from (lon, lat) in degrees, like in WKT
+    fn ring(degrees: &[(f64, f64)]) -> Vec<Point> {
+        return degrees.iter().enumerate().map(|(id, &(lon, lat))| Point::from_degrees(id, lat, lon)).collect();
+    }
+
+    // random points over the whole sphere and dense ones in the given range of latitude and longitude (in degrees)
+    fn points(lat_range: (f64, f64), lon_range: (f64, f64)) -> Vec<Point> {
+        let mut rng = seeded(5);
+        return (0..20_000).map(|id| {
+            if id % 2 == 0 {
+                return point_in(&mut rng, id, (-PI / 2.0, PI / 2.0), (-PI, PI));
+            }
+            return point_in(&mut rng, id, (lat_range.0.to_radians(), lat_range.1.to_radians()), (lon_range.0.to_radians(), lon_range.1.to_radians()));
+        }).collect();
+    }
+
+    // ids found by the tree are exactly the ids of points inside the polygon
+    fn assert_search_matches_contains(polygon: &Polygon, points: &[Point]) -> usize {
+        let tree = KDTree::new(points, 16, Haversine { radius: RADIUS }).unwrap();
+
+        let mut found: Vec<usize> = tree.search_by_polygon(polygon).iter().map(|point| point.id).collect();
+        found.sort_unstable();
+        let expected: Vec<usize> = points.iter().filter(|point| polygon.contains(point)).map(|point| point.id).collect();
+
+        assert_eq!(found, expected);
+        return expected.len();
+    }
+
+    #[test]
+    fn polygon_with_hole() {
+        let exterior = ring(&[(10.0, 40.0), (20.0, 40.0), (20.0, 50.0), (10.0, 50.0), (10.0, 40.0)]);
+        let hole = ring(&[(13.0, 43.0), (17.0, 43.0), (17.0, 47.0), (13.0, 47.0), (13.0, 43.0)]);
+        let polygon = Polygon::new(exterior, vec![hole]).unwrap();
+
+        assert!(polygon.contains(&Point::from_degrees(0, 41.0, 11.0)));
+        assert!(!polygon.contains(&Point::from_degrees(0, 45.0, 15.0)));
+        assert!(!polygon.contains(&Point::from_degrees(0, 45.0, 25.0)));
+
+        let inside = assert_search_matches_contains(&polygon, &points((35.0, 55.0), (5.0, 25.0)));
+        assert!(inside > 1000);
+    }
+
+    #[test]
+    fn polygon_crossing_antimeridian() {
+        let polygon = Polygon::new(ring(&[(170.0, -10.0), (-170.0, -10.0), (-170.0, 10.0), (170.0, 10.0)]), Vec::new()).unwrap();
+
+        let (east, west) = polygon.bounding_boxes();
+        assert!(east.is_some() && west.is_some());
+
+        assert!(polygon.contains(&Point::from_degrees(0, 0.0, 179.9)));
+        assert!(polygon.contains(&Point::from_degrees(0, 0.0, -179.9)));
+        assert!(!polygon.contains(&Point::from_degrees(0, 0.0, 0.0)));
+        assert!(!polygon.contains(&Point::from_degrees(0, 0.0, 160.0)));
+
+        let inside = assert_search_matches_contains(&polygon, &points((-15.0, 15.0), (160.0, 200.0)));
+        assert!(inside > 1000);
+    }
+
+    #[test]
+    fn ring_around_pole() {
+        for pole in [1.0, -1.0] {
+            let vertices: Vec<(f64, f64)> = (0..8).map(|step| (-180.0 + 45.0 * step as f64, pole * 75.0)).collect();
+            let polygon = Polygon::new(ring(&vertices), Vec::new()).unwrap();
+
+            assert!(polygon.contains(&Point::from_degrees(0, pole * 90.0, 0.0)));
+            assert!(polygon.contains(&Point::from_degrees(0, pole * 85.0, 123.0)));
+            assert!(!polygon.contains(&Point::from_degrees(0, pole * 60.0, 10.0)));
+            assert!(!polygon.contains(&Point::from_degrees(0, -pole * 85.0, 10.0)));
+
+            let lat_range = if pole > 0.0 { (65.0, 90.0) } else { (-90.0, -65.0) };
+            let inside = assert_search_matches_contains(&polygon, &points(lat_range, (-180.0, 180.0)));
+            assert!(inside > 1000);
+        }
+    }
+}
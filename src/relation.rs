@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// Value of one column in a row of search results
+pub enum Node {
+    /// Id of the found point
+    Id(usize),
+    /// Numeric value, e.g. distance to the found point. Measure unit = km.
+    NumberFloat(f32),
+}
+
+/// Sink for search results. Each result is a row of Nodes.
+/// 
+/// In the graph database this is the query Relation, here any collection
+/// that can be built from rows can be used to receive results.
+pub trait Relation: FromIterator<Vec<Node>> {}
+
+/// Default relation = plain table of rows
+impl Relation for Vec<Vec<Node>> {}
@@ -1,4 +1,6 @@
-use crate::geo_point::Point;
+use std::f64::consts::PI;
+
+use crate::{geo_error::GeoError, geo_point::Point, geohash::GeohashHelper, sphere_helper::SphereHelper};
 
 /*
 This structure can describe each tree node. It describes a subspace of a tree node. 
@@ -23,6 +25,32 @@ impl SearchBox {
         };
     }
 
+    // box of the whole sphere = box of the root of a tree
+    pub fn whole_sphere() -> Self {
+        return SearchBox {
+            lat_from: -PI / 2.0,
+            lat_to: PI / 2.0,
+            lon_from: -PI,
+            lon_to: PI
+        };
+    }
+
+    // split the box of a tree node in boxes of its children. dimension 0 => latitude, 1 => longitude
+    pub fn split(&self, splitter: f64, dimension: usize) -> (Self, Self) {
+        if dimension == 0 {
+            return (
+                SearchBox { lat_to: splitter, ..*self },
+                SearchBox { lat_from: splitter, ..*self }
+            );
+        }
+        else {
+            return (
+                SearchBox { lon_to: splitter, ..*self },
+                SearchBox { lon_from: splitter, ..*self }
+            );
+        }
+    }
+
     pub fn is_inside(&self, point: &Point) -> bool {
         let cond_1 = self.lat_from <= point.lat;
         let cond_2 = point.lat <= self.lat_to;
@@ -41,4 +69,64 @@ impl SearchBox {
 
         return cond_1 && cond_2 && cond_3 && cond_4;
     }
-}
\ No newline at end of file
+
+    // boxes have at least one common point
+    pub fn intersects(&self, other: &Self) -> bool {
+
+        let cond_1 = self.lat_from <= other.lat_to;
+        let cond_2 = other.lat_from <= self.lat_to;
+        let cond_3 = self.lon_from <= other.lon_to;
+        let cond_4 = other.lon_from <= self.lon_to;
+
+        return cond_1 && cond_2 && cond_3 && cond_4;
+    }
+
+    // gap between latitude ranges of two boxes, 0 if they overlap
+    pub fn lat_gap(&self, other: &Self) -> f64 {
+        return f64::max(0.0, f64::max(other.lat_from - self.lat_to, self.lat_from - other.lat_to));
+    }
+
+    // gap between longitude ranges of two boxes the short way around the sphere, 0 if they overlap
+    pub fn lon_gap(&self, other: &Self) -> f64 {
+        if other.lon_from > self.lon_to {
+            return f64::min(other.lon_from - self.lon_to, self.lon_from + 2.0 * PI - other.lon_to);
+        }
+        if self.lon_from > other.lon_to {
+            return f64::min(self.lon_from - other.lon_to, other.lon_from + 2.0 * PI - self.lon_to);
+        }
+        return 0.0;
+    }
+
+    // largest difference of latitudes between points of two boxes
+    pub fn max_lat_difference(&self, other: &Self) -> f64 {
+        return f64::max(other.lat_to - self.lat_from, self.lat_to - other.lat_from);
+    }
+
+    // largest difference of longitudes between points of two boxes the short way around the sphere, at most PI
+    pub fn max_lon_difference(&self, other: &Self) -> f64 {
+
+        // differences other - self form the range [from, to] => PI if it contains an odd multiple of PI
+        let (from, to) = (other.lon_from - self.lon_to, other.lon_to - self.lon_from);
+        if f64::floor((to - PI) / (2.0 * PI)) >= f64::ceil((from - PI) / (2.0 * PI)) {
+            return PI;
+        }
+
+        // otherwise the normalized differences grow monotonically between the ends
+        return f64::max(f64::abs(SphereHelper::normalize_lon(from)), f64::abs(SphereHelper::normalize_lon(to)));
+    }
+
+    // box of a geohash cell
+    pub fn from_geohash(hash: &str) -> Result<Self, GeoError> {
+        return GeohashHelper::search_box(hash);
+    }
+
+    // box of a single point
+    pub fn from_point(point: &Point) -> Self {
+        return SearchBox {
+            lat_from: point.lat,
+            lat_to: point.lat,
+            lon_from: point.lon,
+            lon_to: point.lon
+        };
+    }
+}
@@ -1,6 +1,6 @@
 use std::f64::consts::PI;
-use optimization::{Minimizer, GradientDescent, NumericalDifferentiation, Func};
 
+use crate::geo_error::GeoError;
 use crate::geo_point::Point;
 use crate::search_box::SearchBox;
 
@@ -11,14 +11,41 @@ pub struct SphereHelper {
 
 impl SphereHelper {
 
+    // bring longitude into [-PI, PI]. Values already in the range stay unchanged
+    pub fn normalize_lon(lon: f64) -> f64 {
+        if (-PI..=PI).contains(&lon) {
+            return lon;
+        }
+        return (lon + PI).rem_euclid(2.0 * PI) - PI;
+    }
+
+    // search distance must be finite and not negative
+    pub fn validate_distance(distance: f64) -> Result<(), GeoError> {
+        if !distance.is_finite() || distance < 0.0 {
+            return Err(GeoError::InvalidDistance(distance));
+        }
+        return Ok(());
+    }
+
+    // sphere radius must be finite and positive
+    pub fn validate_radius(radius: f64) -> Result<(), GeoError> {
+        if !radius.is_finite() || radius <= 0.0 {
+            return Err(GeoError::InvalidRadius(radius));
+        }
+        return Ok(());
+    }
+
     // calculate haversine of value https://en.wikipedia.org/wiki/Versine#ahav
     pub fn hav(x: f64) -> f64 {
         return (1.0 - f64::cos(x)) / 2.0;
     }
 
     // calculate archaversine of value https://en.wikipedia.org/wiki/Versine#ahav
-    pub fn archav(h: f64) -> f64 {
-        return if h >= 0.0 && h <= 1.0 {f64::acos(1.0 - 2.0 * h)} else {0.0};
+    pub fn archav(h: f64) -> Result<f64, GeoError> {
+        if !(0.0..=1.0).contains(&h) {
+            return Err(GeoError::OutOfDomain { function: "archav", value: h });
+        }
+        return Ok(f64::acos(1.0 - 2.0 * h));
     }
 
     // calculate spherical distance https://en.wikipedia.org/wiki/Haversine_formula
@@ -27,19 +54,81 @@ impl SphereHelper {
         let d_lat: f64 = p1.lat - p2.lat;
         let d_lon: f64 = p1.lon - p2.lon;
 
-        // just apply formula 
-        let angle = Self::archav(Self::hav(d_lat) + f64::cos(p1.lat) * f64::cos(p2.lat) * Self::hav(d_lon));
+        // just apply formula. Rounding can push the haversine slightly out of [0, 1] for (nearly) antipodal points
+        let h = f64::clamp(Self::hav(d_lat) + f64::cos(p1.lat) * f64::cos(p2.lat) * Self::hav(d_lon), 0.0, 1.0);
+        let angle = f64::acos(1.0 - 2.0 * h);
 
         return angle * radius;
     }
- 
-    pub fn find_box(point: &Point, distance: f64, radius: f64) -> (Option::<SearchBox>, Option::<SearchBox>) {
+
+    // calculate the smallest spherical distance from the point to any point of the box
+    // this value is used as a lower bound to prune subtrees during nearest neighbour search
+    pub fn distance_to_box(point: &Point, search_box: &SearchBox, radius: f64) -> f64 {
+
+        // the point is inside the longitude range => the closest point lies on the same meridian
+        if search_box.lon_from <= point.lon && point.lon <= search_box.lon_to {
+            let lat = f64::clamp(point.lat, search_box.lat_from, search_box.lat_to);
+            return f64::abs(point.lat - lat) * radius;
+        }
+
+        // otherwise the closest point lies on one of the meridian borders of the box
+        let distance_from = Self::distance_to_meridian(point, search_box.lon_from, search_box.lat_from, search_box.lat_to, radius);
+        let distance_to = Self::distance_to_meridian(point, search_box.lon_to, search_box.lat_from, search_box.lat_to, radius);
+
+        return f64::min(distance_from, distance_to);
+    }
+
+    // calculate the largest spherical distance from the point to any point of the box
+    // the farthest point of the box is the closest one to the antipode of the point
+    pub fn max_distance_to_box(point: &Point, search_box: &SearchBox, radius: f64) -> f64 {
+        let antipode = Point { id: point.id, lat: -point.lat, lon: Self::normalize_lon(point.lon + PI) };
+        return PI * radius - Self::distance_to_box(&antipode, search_box, radius);
+    }
+
+    // upper bound of the spherical distance between any points of two boxes
+    // hav(d) = hav(d_lat) + cos(lat_1) * cos(lat_2) * hav(d_lon) => take the largest differences and the latitudes closest to the equator
+    pub fn max_distance_between_boxes(first: &SearchBox, second: &SearchBox, radius: f64) -> f64 {
+
+        let cos_largest = |search_box: &SearchBox| {
+            if search_box.lat_from <= 0.0 && 0.0 <= search_box.lat_to {
+                return 1.0;
+            }
+            return f64::cos(f64::min(f64::abs(search_box.lat_from), f64::abs(search_box.lat_to)));
+        };
+
+        let h = f64::clamp(Self::hav(first.max_lat_difference(second)) + cos_largest(first) * cos_largest(second) * Self::hav(first.max_lon_difference(second)), 0.0, 1.0);
+        return f64::acos(1.0 - 2.0 * h) * radius;
+    }
+
+    // calculate the smallest spherical distance from the point to a segment of the meridian
+    fn distance_to_meridian(point: &Point, lon: f64, lat_from: f64, lat_to: f64, radius: f64) -> f64 {
+
+        // the meridian is a great circle => position of the closest point on the whole circle.
+        // Values outside [-PI / 2, PI / 2] belong to the opposite meridian
+        let closest_lat = f64::atan2(f64::sin(point.lat), f64::cos(point.lat) * f64::cos(lon - point.lon));
+
+        // along the circle the distance grows from the closest point up to its antipode => check it and both ends of the segment
+        let mut candidates = vec![lat_from, lat_to];
+        if lat_from <= closest_lat && closest_lat <= lat_to {
+            candidates.push(closest_lat);
+        }
+
+        return candidates.into_iter()
+            .map(|lat| Self::distance(point, &Point { id: point.id, lat, lon }, radius))
+            .fold(f64::INFINITY, f64::min);
+    }
+
+    pub fn find_box(point: &Point, distance: f64, radius: f64) -> Result<(Option::<SearchBox>, Option::<SearchBox>), GeoError> {
+
+        point.validate()?;
+        Self::validate_distance(distance)?;
+        Self::validate_radius(radius)?;
 
         let d_lat = distance / radius;
 
         // in case near the nord pole => look around the pole
         if point.lat + d_lat >= PI / 2.0 {
-            return (
+            return Ok((
                 Some(SearchBox { 
                     lat_from: f64::max(point.lat - d_lat, -PI / 2.0),
                     lat_to: PI / 2.0,
@@ -47,12 +136,12 @@ impl SphereHelper {
                     lon_to: PI
                 }),
                 None
-            );
+            ));
         }
 
         // in case near the south pole => look around the pole
         if point.lat - d_lat <= -PI / 2.0 {
-            return (
+            return Ok((
                 Some(SearchBox {
                     lat_from: -PI / 2.0,
                     lat_to: f64::min(point.lat + d_lat, PI / 2.0),
@@ -60,27 +149,17 @@ impl SphereHelper {
                     lon_to: PI
                 }),
                 None
-            );
+            ));
         }
 
-        // minimization block ---------------------------------------------------------------------------------
-        let d_lon_function = NumericalDifferentiation::new(Func(|x: &[f64]| {
-            let dividend = SphereHelper::hav(d_lat) - SphereHelper::hav(x[0] - point.lat);
-            let divisor = f64::cos(x[0]) * f64::cos(point.lat);
-
-            return if f64::abs(divisor) >= f64::abs(dividend) {-1.0 * SphereHelper::archav(dividend / divisor)} else {0.0};
-        }));
-        let minimizer = GradientDescent::new().max_iterations(Some(1000));
-        let solution = minimizer.minimize(&d_lon_function, vec![point.lat]);
-
-        // ----------------------------------------------------------------------------------------------------
-
         // identify maximal possible diffrence in longitude
-        let d_lon = f64::abs(solution.value);
+        // it is reached at the points where meridians touch the circle https://en.wikipedia.org/wiki/Spherical_cap
+        let ratio = f64::sin(d_lat) / f64::cos(point.lat);
+        let d_lon = if ratio < 1.0 { f64::asin(ratio) } else { PI };
 
         // if the distance is so large that we can go over the sphere => look over sphere
         if d_lon >= PI {
-            return (
+            return Ok((
                 Some(SearchBox {
                     lat_from: point.lat - d_lat,
                     lat_to: point.lat + d_lat,
@@ -88,7 +167,7 @@ impl SphereHelper {
                     lon_to: PI
                 }),
                 None
-            );
+            ));
         }
 
         // if the point if near the right border => look near the left border, because it's a sphere
@@ -96,7 +175,7 @@ impl SphereHelper {
 
             let delta = point.lon + d_lon - PI;
 
-            return (
+            return Ok((
                 Some(SearchBox {
                     lat_from: point.lat - d_lat,
                     lat_to: point.lat + d_lat,
@@ -109,7 +188,7 @@ impl SphereHelper {
                     lon_from: -PI,
                     lon_to: f64::min(delta - PI, point.lon - d_lon)
                 })
-            );
+            ));
         }
 
         // if the point if near the left border => look near the right border, because it's a sphere
@@ -117,7 +196,7 @@ impl SphereHelper {
 
             let delta = d_lon - point.lon - PI;
 
-            return (
+            return Ok((
                 Some(SearchBox {
                     lat_from: point.lat - d_lat,
                     lat_to: point.lat + d_lat,
@@ -130,11 +209,11 @@ impl SphereHelper {
                     lon_from: f64::max(PI - delta, point.lon + d_lon),
                     lon_to: PI
                 })
-            );
+            ));
         }
 
         // in other cases just return one SearchBox
-        return (
+        return Ok((
             Some(SearchBox { 
                 lat_from: point.lat - d_lat,
                 lat_to: point.lat + d_lat,
@@ -142,7 +221,238 @@ impl SphereHelper {
                 lon_to: point.lon + d_lon
             }),
             None
+        ));
+    }
+
+    // SearchBoxes that contain all points within the distance from any point of the box, the same way as find_box for a point
+    // the difference in longitude is the largest for the points of the box that are the closest to a pole
+    pub fn find_box_around_box(search_box: &SearchBox, distance: f64, radius: f64) -> (Option::<SearchBox>, Option::<SearchBox>) {
+
+        let d_lat = distance / radius;
+        let (lat_from, lat_to) = (search_box.lat_from - d_lat, search_box.lat_to + d_lat);
+
+        // in case near a pole => look around the pole
+        if lat_to >= PI / 2.0 || lat_from <= -PI / 2.0 {
+            return (
+                Some(SearchBox {
+                    lat_from: f64::max(lat_from, -PI / 2.0),
+                    lat_to: f64::min(lat_to, PI / 2.0),
+                    lon_from: -PI,
+                    lon_to: PI
+                }),
+                None
+            );
+        }
+
+        let ratio = f64::sin(d_lat) / f64::cos(f64::max(f64::abs(search_box.lat_from), f64::abs(search_box.lat_to)));
+        let d_lon = if ratio < 1.0 { f64::asin(ratio) } else { PI };
+
+        return Self::wrap_searchbox(lat_from, lat_to, search_box.lon_from - d_lon, search_box.lon_to + d_lon);
+    }
+
+    // SearchBoxes for a range of longitudes that can go over the antimeridian by at most one turn
+    pub fn wrap_searchbox(lat_from: f64, lat_to: f64, lon_from: f64, lon_to: f64) -> (Option::<SearchBox>, Option::<SearchBox>) {
+
+        // the range goes around the whole sphere => look over sphere
+        if lon_to - lon_from >= 2.0 * PI {
+            return (Some(SearchBox { lat_from, lat_to, lon_from: -PI, lon_to: PI }), None);
+        }
+
+        if lon_to > PI {
+            return (
+                Some(SearchBox { lat_from, lat_to, lon_from, lon_to: PI }),
+                Some(SearchBox { lat_from, lat_to, lon_from: -PI, lon_to: lon_to - 2.0 * PI })
+            );
+        }
+
+        if lon_from < -PI {
+            return (
+                Some(SearchBox { lat_from, lat_to, lon_from: -PI, lon_to }),
+                Some(SearchBox { lat_from, lat_to, lon_from: lon_from + 2.0 * PI, lon_to: PI })
+            );
+        }
+
+        return (Some(SearchBox { lat_from, lat_to, lon_from, lon_to }), None);
+    }
+
+    // construct SearchBoxes for the box between two corners (cornerWest, cornerEast like in Wikidata)
+    // if the west corner is to the east of the east corner => the box crosses the antimeridian and is split in two parts
+    pub fn construct_searchbox(corner_west: &Point, corner_east: &Point) -> Result<(Option::<SearchBox>, Option::<SearchBox>), GeoError> {
+
+        corner_west.validate()?;
+        corner_east.validate()?;
+
+        let lat_from = f64::min(corner_west.lat, corner_east.lat);
+        let lat_to = f64::max(corner_west.lat, corner_east.lat);
+
+        if corner_west.lon <= corner_east.lon {
+            return Ok((
+                Some(SearchBox {
+                    lat_from,
+                    lat_to,
+                    lon_from: corner_west.lon,
+                    lon_to: corner_east.lon
+                }),
+                None
+            ));
+        }
+
+        return Ok((
+            Some(SearchBox {
+                lat_from,
+                lat_to,
+                lon_from: corner_west.lon,
+                lon_to: PI
+            }),
+            Some(SearchBox {
+                lat_from,
+                lat_to,
+                lon_from: -PI,
+                lon_to: corner_east.lon
+            })
+        ));
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use crate::{geo_error::GeoError, geo_point::Point, search_box::SearchBox};
+    use super::SphereHelper;
+
+    // point at the angular distance from the start in the direction of the bearing
+    fn destination(start: &Point, bearing: f64, angle: f64) -> Point {
+        let lat = f64::asin(f64::sin(start.lat) * f64::cos(angle) + f64::cos(start.lat) * f64::sin(angle) * f64::cos(bearing));
+        let lon = start.lon + f64::atan2(
+            f64::sin(bearing) * f64::sin(angle) * f64::cos(start.lat),
+            f64::cos(angle) - f64::sin(start.lat) * f64::sin(lat)
         );
+
+        return Point { id: 0, lat, lon: SphereHelper::normalize_lon(lon) };
     }
 
-}
\ No newline at end of file
+    // centers of caps over the whole sphere, including poles and the antimeridian
+    fn centers() -> Vec<Point> {
+        let mut centers = Vec::new();
+        for lat_step in -10..=10 {
+            for lon_step in -12..=12 {
+                centers.push(Point { id: 0, lat: lat_step as f64 * PI / 20.0, lon: lon_step as f64 * PI / 12.0 });
+            }
+        }
+        centers.push(Point { id: 0, lat: 1.5, lon: PI - 1e-3 });
+        centers.push(Point { id: 0, lat: -1.5, lon: -PI + 1e-3 });
+        return centers;
+    }
+
+    #[test]
+    fn find_box_contains_whole_cap() {
+        let radius = 6371.0;
+        let eps = 1e-9;
+
+        for center in centers() {
+            for angle in [1e-4, 0.01, 0.1, 0.5, 1.0, 1.5, 2.0] {
+                let (box_0, box_1) = SphereHelper::find_box(&center, angle * radius, radius).unwrap();
+
+                for bearing_step in 0..360 {
+                    for fraction in [0.5, 1.0] {
+                        let point = destination(&center, bearing_step as f64 * PI / 180.0, angle * fraction);
+
+                        let inside = [box_0, box_1].iter().flatten().any(|target| {
+                            target.lat_from - eps <= point.lat && point.lat <= target.lat_to + eps &&
+                            target.lon_from - eps <= point.lon && point.lon <= target.lon_to + eps
+                        });
+
+                        assert!(inside, "{:?} at angle {} is outside of {:?} {:?}", point, angle, box_0, box_1);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn find_box_longitude_bound_is_tight() {
+        let radius = 1.0;
+        let center = Point { id: 0, lat: 0.8, lon: 0.3 };
+        let angle = 0.2;
+
+        // the cap touches meridians at the analytic longitude => no point of the border goes further
+        let max_d_lon = (0..36_000).map(|step| {
+            let point = destination(&center, step as f64 * PI / 18_000.0, angle);
+            return f64::abs(point.lon - center.lon);
+        }).fold(0.0, f64::max);
+
+        let (box_0, box_1) = SphereHelper::find_box(&center, angle, radius).unwrap();
+        let target = box_0.unwrap();
+
+        assert!(box_1.is_none());
+        assert!(f64::abs(target.lon_to - center.lon - max_d_lon) < 1e-6);
+        assert!(f64::abs(center.lon - target.lon_from - max_d_lon) < 1e-6);
+    }
+
+    #[test]
+    fn find_box_splits_on_antimeridian() {
+        let center = Point { id: 0, lat: 0.1, lon: PI - 0.05 };
+        let (box_0, box_1) = SphereHelper::find_box(&center, 0.1, 1.0).unwrap();
+
+        let (east, west) = (box_0.unwrap(), box_1.unwrap());
+        assert_eq!(east.lon_to, PI);
+        assert_eq!(west.lon_from, -PI);
+        assert!(west.lon_to > -PI + 0.04);
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        let center = Point { id: 7, lat: 0.1, lon: 0.2 };
+
+        assert_eq!(SphereHelper::archav(1.5), Err(GeoError::OutOfDomain { function: "archav", value: 1.5 }));
+        assert_eq!(SphereHelper::find_box(&center, -1.0, 1.0).err(), Some(GeoError::InvalidDistance(-1.0)));
+        assert_eq!(SphereHelper::find_box(&center, 1.0, 0.0).err(), Some(GeoError::InvalidRadius(0.0)));
+
+        let outside = Point { id: 8, lat: 2.0, lon: 0.0 };
+        assert_eq!(SphereHelper::find_box(&outside, 1.0, 1.0).err(), Some(GeoError::LatitudeOutOfRange { id: 8, lat: 2.0 }));
+    }
+
+    #[test]
+    fn antipodal_distance_is_half_circumference() {
+        let p1 = Point { id: 0, lat: 0.3, lon: 0.5 };
+        let p2 = Point { id: 1, lat: -0.3, lon: 0.5 - PI };
+
+        assert!(f64::abs(SphereHelper::distance(&p1, &p2, 1.0) - PI) < 1e-9);
+    }
+
+    #[test]
+    fn find_box_around_box_contains_caps_of_all_points() {
+        let radius = 6371.0;
+        let eps = 1e-9;
+
+        for center in centers() {
+            let search_box = SearchBox::new(f64::max(center.lat - 0.03, -PI / 2.0), center.lat, center.lon, f64::min(center.lon + 0.04, PI));
+            let corners = [
+                Point { id: 0, lat: search_box.lat_from, lon: search_box.lon_from },
+                Point { id: 0, lat: search_box.lat_from, lon: search_box.lon_to },
+                Point { id: 0, lat: search_box.lat_to, lon: search_box.lon_from },
+                Point { id: 0, lat: search_box.lat_to, lon: search_box.lon_to }
+            ];
+
+            for angle in [1e-4, 0.01, 0.1, 0.5, 1.5] {
+                let (box_0, box_1) = SphereHelper::find_box_around_box(&search_box, angle * radius, radius);
+
+                for corner in corners.iter() {
+                    for bearing_step in 0..360 {
+                        let point = destination(corner, bearing_step as f64 * PI / 180.0, angle);
+
+                        let inside = [box_0, box_1].iter().flatten().any(|target| {
+                            target.lat_from - eps <= point.lat && point.lat <= target.lat_to + eps &&
+                            target.lon_from - eps <= point.lon && point.lon <= target.lon_to + eps
+                        });
+
+                        assert!(inside, "{:?} at angle {} from {:?} is outside of {:?} {:?}", point, angle, search_box, box_0, box_1);
+                    }
+                }
+            }
+        }
+    }
+
+}
@@ -0,0 +1,106 @@
+use std::f64::consts::PI;
+use std::path::{Path, PathBuf};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{distance_metric::DistanceMetric, geo_point::Point, kd_tree::KDTree, sphere_helper::SphereHelper};
+
+/*
+Helpers shared by the tests of all modules: seeded random points, query centers and temporary files.
+Every test generates its data the same way => results are reproducible and tests of different modules are comparable
+*/
+
+// radius of the Earth in km, used by tests of spherical metrics
+pub const RADIUS: f64 = 6371.0;
+
+pub fn seeded(seed: u64) -> StdRng {
+    return StdRng::seed_from_u64(seed);
+}
+
+// point at a random position inside the ranges (in radians). The range of longitude can go over the antimeridian
+pub fn point_in(rng: &mut StdRng, id: usize, lat_range: (f64, f64), lon_range: (f64, f64)) -> Point {
+    let lat = rng.gen_range(lat_range.0..=lat_range.1);
+    let lon = SphereHelper::normalize_lon(rng.gen_range(lon_range.0..=lon_range.1));
+    return Point { id, lat, lon };
+}
+
+// random points over the whole sphere, ids from 0
+pub fn uniform_points(rng: &mut StdRng, amount: usize) -> Vec<Point> {
+    return (0..amount).map(|id| point_in(rng, id, (-PI / 2.0, PI / 2.0), (-PI, PI))).collect();
+}
+
+// random points over the whole sphere and dense clusters around both poles and the antimeridian, ids from 0
+pub fn clustered_points(rng: &mut StdRng, amount: usize) -> Vec<Point> {
+    return (0..amount).map(|id| {
+        let (lat_range, lon_range) = match id % 4 {
+            0 => ((-PI / 2.0, PI / 2.0), (-PI, PI)),
+            1 => ((PI / 2.0 - 0.05, PI / 2.0), (-PI, PI)),
+            2 => ((-PI / 2.0, -PI / 2.0 + 0.05), (-PI, PI)),
+            _ => ((-1.0, 1.0), (PI - 0.05, PI + 0.05))
+        };
+        return point_in(rng, id, lat_range, lon_range);
+    }).collect();
+}
+
+// query centers on the poles, next to them and on both sides of the antimeridian
+pub fn centers() -> Vec<Point> {
+    return vec![
+        Point { id: 0, lat: PI / 2.0, lon: 0.0 },
+        Point { id: 0, lat: -PI / 2.0, lon: 1.0 },
+        Point { id: 0, lat: PI / 2.0 - 0.01, lon: PI },
+        Point { id: 0, lat: -PI / 2.0 + 0.02, lon: -2.0 },
+        Point { id: 0, lat: 0.3, lon: PI - 1e-4 },
+        Point { id: 0, lat: -0.2, lon: -PI },
+        Point { id: 0, lat: 0.0, lon: 0.0 }
+    ];
+}
+
+// radius search, count and k nearest points of a tree around the centers compared with a check of every point
+pub fn assert_tree_matches_brute_force<M: DistanceMetric>(metric: M, points: &[Point], centers: &[Point], distances: &[f64]) {
+    let tree = KDTree::new(points, 8, metric).unwrap();
+
+    for center in centers {
+        for &distance in distances {
+            let mut found: Vec<usize> = tree.search_by_distance(center, distance).unwrap().iter().map(|point| point.id).collect();
+            found.sort_unstable();
+            let mut expected: Vec<usize> = points.iter().filter(|point| metric.distance(center, point) <= distance).map(|point| point.id).collect();
+            expected.sort_unstable();
+
+            assert_eq!(found, expected, "{:?} around {:?} within {}", metric, center, distance);
+            assert_eq!(tree.count_by_distance(center, distance).unwrap(), expected.len(), "{:?} around {:?} within {}", metric, center, distance);
+        }
+
+        // points at the same distance can come in any order => distances are compared
+        for k in [1, 10, 100] {
+            let mut expected: Vec<f64> = points.iter().map(|point| metric.distance(center, point)).collect();
+            expected.sort_by(f64::total_cmp);
+            expected.truncate(k);
+            let found: Vec<f64> = tree.nearest(center, k).unwrap().iter().map(|point| metric.distance(center, point)).collect();
+
+            assert_eq!(found, expected, "{:?}: {} nearest to {:?}", metric, k, center);
+        }
+    }
+}
+
+// file in the temporary directory, unique for the test and the process. Is removed when dropped, also if the test fails
+pub struct TempFile {
+    path: PathBuf
+}
+
+impl TempFile {
+
+    pub fn new(name: &str) -> Self {
+        return TempFile { path: std::env::temp_dir().join(format!("geo_spacial_search_{}_{}.bin", name, std::process::id())) };
+    }
+
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // the file may not exist if the test failed before writing it
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,477 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+use crate::{distance_metric::{self, DistanceMetric}, geo_error::GeoError, geohash::GeohashHelper, geo_point::{Locatable, Point}, search_box::SearchBox, sphere_helper::SphereHelper};
+
+/*
+Read access to a KD-Tree stored as a flat array of nodes.
+Is implemented by KDTree (nodes in memory) and MappedKDTree (nodes are read in place from a file),
+so both are searched by the same code
+*/
+pub trait TreeView {
+    type Value: Locatable;
+    type Item<'a>: Borrow<Self::Value> where Self: 'a;
+    type Metric: DistanceMetric;
+
+    fn root(&self) -> usize;
+
+    // way to measure distances the tree was built for
+    fn metric(&self) -> Self::Metric;
+
+    // splitter, dimension, left child and right child of an inner node. None => the node is a leaf
+    fn split(&self, node_idx: usize) -> Option<(f64, usize, usize, usize)>;
+
+    // number of points in the leaf
+    fn leaf_len(&self, node_idx: usize) -> usize;
+
+    // number of points in the whole subtree of the node
+    fn subtree_len(&self, node_idx: usize) -> usize;
+
+    // item of the leaf at the position
+    fn leaf_item(&self, node_idx: usize, position: usize) -> Self::Item<'_>;
+}
+
+/*
+Lazy walk over the points inside one or both SearchBoxes (the second one is used for the other side of the antimeridian).
+Nodes are visited in the same order as by a recursive search, but leaves are read only when the next point is requested =>
+the caller can stop early or stream results into its own collection
+*/
+pub struct SearchIter<'a, V: TreeView> {
+    view: &'a V,
+    targets: [Option::<SearchBox>; 2], // targets that are not searched yet
+    target: Option::<SearchBox>, // current target
+    stack: Vec::<(usize, SearchBox, bool)>, // nodes to visit with their boxes. true => the whole node is inside the target
+    leaf: Option::<(usize, usize, bool)> // current leaf, next position in it, take everything
+}
+
+impl<'a, V: TreeView> Iterator for SearchIter<'a, V> {
+    type Item = V::Item<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // read the current leaf
+            if let Some((node_idx, position, take_all)) = self.leaf {
+                if position < self.view.leaf_len(node_idx) {
+                    self.leaf = Some((node_idx, position + 1, take_all));
+                    let item = self.view.leaf_item(node_idx, position);
+                    if take_all || self.target.is_some_and(|target| target.is_inside(item.borrow().point())) {
+                        return Some(item);
+                    }
+                    continue;
+                }
+                self.leaf = None;
+            }
+
+            let (node_idx, current_box, inside) = match self.stack.pop() {
+                Some(entry) => entry,
+                None => {
+                    // current target is done => start the next one
+                    self.target = self.targets.iter_mut().find_map(|target| target.take());
+                    self.target?;
+                    self.stack.push((self.view.root(), SearchBox::whole_sphere(), false));
+                    continue;
+                }
+            };
+
+            let target = self.target?;
+
+            // the whole node is inside the target => take everything
+            let inside = inside || SearchBox::nested_box(&current_box, &target);
+
+            match self.view.split(node_idx) {
+                None => {
+                    self.leaf = Some((node_idx, 0, inside));
+                },
+                Some((splitter, dimension, left_child, right_child)) => {
+                    let (left_box, right_box) = current_box.split(splitter, dimension);
+                    let (target_from, target_to) = if dimension == 0 { (target.lat_from, target.lat_to) } else { (target.lon_from, target.lon_to) };
+
+                    // right child is pushed first => the left one is visited first
+                    // points equal to the splitter can be on both sides
+                    if inside || splitter <= target_to {
+                        self.stack.push((right_child, right_box, inside));
+                    }
+                    if inside || target_from <= splitter {
+                        self.stack.push((left_child, left_box, inside));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// all points inside one or both SearchBoxes (the second one is used for the other side of the antimeridian)
+pub fn search<V: TreeView>(view: &V, targets: (Option::<SearchBox>, Option::<SearchBox>)) -> SearchIter<'_, V> {
+    return SearchIter {
+        view,
+        targets: [targets.0, targets.1],
+        target: None,
+        stack: Vec::new(),
+        leaf: None
+    };
+}
+
+// points within the distance around the point together with their distances, in the order of the tree
+pub fn search_by_distance_iter<'a, V: TreeView>(view: &'a V, point: &Point, distance: f64) -> Result<impl Iterator<Item = (V::Item<'a>, f64)> + 'a, GeoError> {
+
+    let metric = view.metric();
+    let candidates = search(view, metric.find_box(point, distance)?);
+    let center = *point;
+
+    // filter candidates and get the answers
+    return Ok(candidates.map(move |candidate| {
+        let candidate_distance = metric.distance(&center, candidate.borrow().point());
+        (candidate, candidate_distance)
+    }).filter(move |&(_, candidate_distance)| candidate_distance <= distance));
+}
+
+// points within the distance around the point together with their distances. If sorted => the closest points come first
+pub fn search_by_distance_with_distances<'a, V: TreeView>(view: &'a V, point: &Point, distance: f64, sorted: bool) -> Result<Vec::<(V::Item<'a>, f64)>, GeoError> {
+
+    let mut results: Vec::<(V::Item<'a>, f64)> = search_by_distance_iter(view, point, distance)?.collect();
+
+    if sorted {
+        results.sort_by(|(_, left), (_, right)| left.partial_cmp(right).unwrap_or(Ordering::Equal));
+    }
+
+    return Ok(results);
+}
+
+// number of points inside one or both SearchBoxes. Nodes that are completely inside a target are counted without visiting them
+pub fn count<V: TreeView>(view: &V, targets: (Option::<SearchBox>, Option::<SearchBox>)) -> usize {
+    return [targets.0, targets.1].iter().flatten().map(|target| {
+        count_node(view, view.root(), &SearchBox::whole_sphere(), target)
+    }).sum();
+}
+
+// number of points within the distance around the point
+pub fn count_by_distance<V: TreeView>(view: &V, point: &Point, distance: f64) -> Result<usize, GeoError> {
+
+    point.validate()?;
+    SphereHelper::validate_distance(distance)?;
+
+    // the tree is pruned by the boxes around the circle first, like in a radius search
+    let (target_0, target_1) = view.metric().find_box(point, distance)?;
+    return Ok([target_0, target_1].iter().flatten().map(|target| {
+        count_within(view, view.root(), &SearchBox::whole_sphere(), target, point, distance)
+    }).sum());
+}
+
+// number of points inside the box between two corners (cornerWest, cornerEast like in Wikidata)
+pub fn count_by_box<V: TreeView>(view: &V, corner_west: &Point, corner_east: &Point) -> Result<usize, GeoError> {
+    return Ok(count(view, SphereHelper::construct_searchbox(corner_west, corner_east)?));
+}
+
+// all pairs of points (first, second, distance) from two trees that are within the distance from each other
+// both trees are walked together => pairs of nodes whose boxes are too far from each other are skipped at once,
+// pairs of nodes whose boxes are close enough are taken at once
+pub fn join_by_distance<'a, 'b, A: TreeView, B: TreeView<Metric = A::Metric>>(first: &'a A, second: &'b B, distance: f64, emit: &mut impl FnMut(A::Item<'a>, B::Item<'b>, f64)) -> Result<(), GeoError> {
+
+    SphereHelper::validate_distance(distance)?;
+    if first.metric() != second.metric() {
+        return Err(GeoError::MetricMismatch {
+            found: distance_metric::describe_metric(A::Metric::CODE, second.metric().radius()),
+            expected: distance_metric::describe_metric(A::Metric::CODE, first.metric().radius())
+        });
+    }
+
+    join_nodes(first, (first.root(), SearchBox::whole_sphere()), second, &[(second.root(), SearchBox::whole_sphere())], distance, false, emit);
+
+    return Ok(());
+}
+
+// all pairs of points of one tree within the distance from each other. Every unordered pair is reported once
+pub fn pairs_within<'a, V: TreeView>(view: &'a V, distance: f64, emit: &mut impl FnMut(V::Item<'a>, V::Item<'a>, f64)) -> Result<(), GeoError> {
+
+    SphereHelper::validate_distance(distance)?;
+
+    join_nodes(view, (view.root(), SearchBox::whole_sphere()), view, &[(view.root(), SearchBox::whole_sphere())], distance, true, emit);
+
+    return Ok(());
+}
+
+// all points inside the box between two corners. Corners are defined the same way as in Wikidata (cornerWest, cornerEast)
+pub fn search_by_box<'a, V: TreeView>(view: &'a V, corner_west: &Point, corner_east: &Point) -> Result<SearchIter<'a, V>, GeoError> {
+    return Ok(search(view, SphereHelper::construct_searchbox(corner_west, corner_east)?));
+}
+
+// all points inside the geohash cell of the prefix. Points on a border between cells are taken only by the cell that contains them
+pub fn search_by_geohash<'a, V: TreeView>(view: &'a V, prefix: &str) -> Result<impl Iterator<Item = V::Item<'a>> + 'a, GeoError> {
+    let target = GeohashHelper::search_box(prefix)?;
+    let prefix = prefix.to_ascii_lowercase();
+    return Ok(search(view, (Some(target), None)).filter(move |candidate| GeohashHelper::contains(&prefix, candidate.borrow().point())));
+}
+
+fn count_node<V: TreeView>(view: &V, node_idx: usize, current_box: &SearchBox, target: &SearchBox) -> usize {
+
+    // the whole node is inside the target => the size of the subtree is known
+    if SearchBox::nested_box(current_box, target) {
+        return view.subtree_len(node_idx);
+    }
+
+    match view.split(node_idx) {
+        None => {
+            return (0..view.leaf_len(node_idx)).filter(|&position| target.is_inside(view.leaf_item(node_idx, position).borrow().point())).count();
+        },
+        Some((splitter, dimension, left_child, right_child)) => {
+            let (left_box, right_box) = current_box.split(splitter, dimension);
+            let (target_from, target_to) = if dimension == 0 { (target.lat_from, target.lat_to) } else { (target.lon_from, target.lon_to) };
+
+            // points equal to the splitter can be on both sides
+            let mut total = 0;
+            if target_from <= splitter {
+                total += count_node(view, left_child, &left_box, target);
+            }
+            if splitter <= target_to {
+                total += count_node(view, right_child, &right_box, target);
+            }
+            return total;
+        }
+    }
+}
+
+// distances lose precision in acos => nodes near the border of the circle are checked point by point
+const BORDER_MARGIN: f64 = 1e-6;
+
+// descent is the same as in count_node. Only nodes inside the target are compared with the circle
+fn count_within<V: TreeView>(view: &V, node_idx: usize, current_box: &SearchBox, target: &SearchBox, point: &Point, distance: f64) -> usize {
+
+    let metric = view.metric();
+    let inside = SearchBox::nested_box(current_box, target);
+
+    if inside {
+        let margin = BORDER_MARGIN * metric.radius();
+
+        // the node is out of the circle => nothing to count
+        if metric.distance_to_box(point, current_box) > distance + margin {
+            return 0;
+        }
+
+        // the whole node is inside the circle => the size of the subtree is known
+        if metric.max_distance_to_box(point, current_box) + margin <= distance {
+            return view.subtree_len(node_idx);
+        }
+    }
+
+    match view.split(node_idx) {
+        None => {
+            return (0..view.leaf_len(node_idx)).filter(|&position| {
+                let candidate = view.leaf_item(node_idx, position);
+                (inside || target.is_inside(candidate.borrow().point())) && metric.distance(point, candidate.borrow().point()) <= distance
+            }).count();
+        },
+        Some((splitter, dimension, left_child, right_child)) => {
+            let (left_box, right_box) = current_box.split(splitter, dimension);
+            let (target_from, target_to) = if dimension == 0 { (target.lat_from, target.lat_to) } else { (target.lon_from, target.lon_to) };
+
+            // points equal to the splitter can be on both sides
+            let mut total = 0;
+            if target_from <= splitter {
+                total += count_within(view, left_child, &left_box, target, point, distance);
+            }
+            if splitter <= target_to {
+                total += count_within(view, right_child, &right_box, target, point, distance);
+            }
+            return total;
+        }
+    }
+}
+
+// nodes of the second tree that can have pairs with the node of the first tree go down together with it, one level per step
+// nodes outside the boxes around the node are dropped, nodes that are close enough as a whole give all their pairs at once.
+// same_tree => both trees are one tree and every unordered pair is reported once: a node paired with itself gives the pairs
+// of its left child with both children to the left child and the pairs of its right child with itself to the right child
+fn join_nodes<'a, 'b, A: TreeView, B: TreeView<Metric = A::Metric>>(first: &'a A, (first_idx, first_box): (usize, SearchBox), second: &'b B, candidates: &[(usize, SearchBox)], distance: f64, same_tree: bool, emit: &mut impl FnMut(A::Item<'a>, B::Item<'b>, f64)) {
+
+    let metric = first.metric();
+    let margin = BORDER_MARGIN * metric.radius();
+    let targets = metric.find_box_around_box(&first_box, distance);
+
+    // no node can be close enough as a whole while the node of the first tree is larger than the distance
+    let small = metric.max_distance_between_boxes(&first_box, &first_box) + margin <= distance;
+
+    let mut close = Vec::with_capacity(candidates.len());
+    for &(candidate_idx, candidate_box) in candidates {
+        if !intersects_any(&targets, &candidate_box) {
+            continue;
+        }
+
+        // pairs of a node with itself are found in its children
+        if small && !(same_tree && candidate_idx == first_idx) && metric.max_distance_between_boxes(&first_box, &candidate_box) + margin <= distance {
+            join_all(first, first_idx, second, candidate_idx, emit);
+            continue;
+        }
+        close.push((candidate_idx, candidate_box));
+    }
+
+    if close.is_empty() {
+        return;
+    }
+
+    match first.split(first_idx) {
+        None => {
+            join_leaf(first, first_idx, second, close, distance, same_tree, emit);
+        },
+        Some((splitter, dimension, left_child, right_child)) => {
+
+            // candidates go one level down, leaves stay as they are
+            let mut next_candidates = Vec::with_capacity(2 * close.len());
+            let mut itself = false;
+            for (candidate_idx, candidate_box) in close {
+                if same_tree && candidate_idx == first_idx {
+                    itself = true;
+                    continue;
+                }
+                match second.split(candidate_idx) {
+                    None => next_candidates.push((candidate_idx, candidate_box)),
+                    Some((candidate_splitter, candidate_dimension, candidate_left, candidate_right)) => {
+                        let (left_box, right_box) = candidate_box.split(candidate_splitter, candidate_dimension);
+                        next_candidates.extend([(candidate_left, left_box), (candidate_right, right_box)]);
+                    }
+                }
+            }
+
+            let (left_box, right_box) = first_box.split(splitter, dimension);
+            let (mut left_candidates, mut right_candidates) = (next_candidates.clone(), next_candidates);
+            if itself {
+                left_candidates.extend([(left_child, left_box), (right_child, right_box)]);
+                right_candidates.push((right_child, right_box));
+            }
+
+            join_nodes(first, (left_child, left_box), second, &left_candidates, distance, same_tree, emit);
+            join_nodes(first, (right_child, right_box), second, &right_candidates, distance, same_tree, emit);
+        }
+    }
+}
+
+// pairs of the leaf of the first tree with the subtrees of the second tree
+// points of a leaf fill only a part of its box => leaves of both trees are compared by the boxes of their points
+fn join_leaf<'a, 'b, A: TreeView, B: TreeView<Metric = A::Metric>>(first: &'a A, first_idx: usize, second: &'b B, candidates: Vec::<(usize, SearchBox)>, distance: f64, same_tree: bool, emit: &mut impl FnMut(A::Item<'a>, B::Item<'b>, f64)) {
+
+    let metric = first.metric();
+    let margin = BORDER_MARGIN * metric.radius();
+
+    let first_points = leaf_points(first, first_idx);
+    let first_box = match bounding_box(first_points.iter().copied()) {
+        Some(first_box) => first_box,
+        None => return
+    };
+    let targets = metric.find_box_around_box(&first_box, distance);
+    let small = metric.max_distance_between_boxes(&first_box, &first_box) + margin <= distance;
+
+    // leaves of the second tree that can have pairs with the leaf, with the boxes of their points
+    let mut leaves = Vec::new();
+    let mut stack = candidates;
+    while let Some((node_idx, node_box)) = stack.pop() {
+        if !intersects_any(&targets, &node_box) {
+            continue;
+        }
+
+        match second.split(node_idx) {
+            None => {
+                let points = (0..second.leaf_len(node_idx)).map(|position| *second.leaf_item(node_idx, position).borrow().point());
+                let points_box = match bounding_box(points) {
+                    Some(points_box) if intersects_any(&targets, &points_box) => points_box,
+                    _ => continue
+                };
+                if small && !(same_tree && node_idx == first_idx) && metric.max_distance_between_boxes(&first_box, &points_box) + margin <= distance {
+                    join_all(first, first_idx, second, node_idx, emit);
+                    continue;
+                }
+                leaves.push((node_idx, points_box));
+            },
+            Some((splitter, dimension, left_child, right_child)) => {
+                if small && metric.max_distance_between_boxes(&first_box, &node_box) + margin <= distance {
+                    join_all(first, first_idx, second, node_idx, emit);
+                    continue;
+                }
+                let (left_box, right_box) = node_box.split(splitter, dimension);
+                stack.extend([(left_child, left_box), (right_child, right_box)]);
+            }
+        }
+    }
+
+    // every point of the leaf is compared only with points inside its boxes, the same way as in a radius search
+    for (first_position, first_point) in first_points.iter().enumerate() {
+        if leaves.is_empty() {
+            break;
+        }
+
+        let point_targets = match metric.find_box(first_point, distance) {
+            Ok(point_targets) => point_targets,
+            Err(_) => continue
+        };
+
+        for &(second_idx, points_box) in leaves.iter() {
+            if !intersects_any(&point_targets, &points_box) {
+                continue;
+            }
+            // the same leaf => only pairs with the following points
+            let second_from = if same_tree && second_idx == first_idx { first_position + 1 } else { 0 };
+            for second_position in second_from..second.leaf_len(second_idx) {
+                let second_item = second.leaf_item(second_idx, second_position);
+                let second_point = *second_item.borrow().point();
+                if !point_targets.0.is_some_and(|target| target.is_inside(&second_point)) && !point_targets.1.is_some_and(|target| target.is_inside(&second_point)) {
+                    continue;
+                }
+                let pair_distance = metric.distance(first_point, &second_point);
+                if pair_distance <= distance {
+                    emit(first.leaf_item(first_idx, first_position), second_item, pair_distance);
+                }
+            }
+        }
+    }
+}
+
+// all pairs of points of two subtrees
+fn join_all<'a, 'b, A: TreeView, B: TreeView<Metric = A::Metric>>(first: &'a A, first_idx: usize, second: &'b B, second_idx: usize, emit: &mut impl FnMut(A::Item<'a>, B::Item<'b>, f64)) {
+
+    let metric = first.metric();
+    let (first_leaves, second_leaves) = (leaves(first, first_idx), leaves(second, second_idx));
+    let second_points: Vec::<Vec::<Point>> = second_leaves.iter().map(|&leaf_idx| leaf_points(second, leaf_idx)).collect();
+
+    for &first_leaf in first_leaves.iter() {
+        for first_position in 0..first.leaf_len(first_leaf) {
+            let first_point = *first.leaf_item(first_leaf, first_position).borrow().point();
+            for (&second_leaf, points) in second_leaves.iter().zip(second_points.iter()) {
+                for (second_position, second_point) in points.iter().enumerate() {
+                    emit(first.leaf_item(first_leaf, first_position), second.leaf_item(second_leaf, second_position), metric.distance(&first_point, second_point));
+                }
+            }
+        }
+    }
+}
+
+// leaves of the subtree of the node
+fn leaves<V: TreeView>(view: &V, node_idx: usize) -> Vec::<usize> {
+    let mut leaves = Vec::new();
+    let mut stack = vec![node_idx];
+    while let Some(node_idx) = stack.pop() {
+        match view.split(node_idx) {
+            None => leaves.push(node_idx),
+            Some((_, _, left_child, right_child)) => stack.extend([right_child, left_child])
+        }
+    }
+    return leaves;
+}
+
+// copies of the points of the leaf => they are read once for many pairs
+fn leaf_points<V: TreeView>(view: &V, node_idx: usize) -> Vec::<Point> {
+    return (0..view.leaf_len(node_idx)).map(|position| *view.leaf_item(node_idx, position).borrow().point()).collect();
+}
+
+// smallest box that contains the points, None if there are no points
+fn bounding_box(mut points: impl Iterator<Item = Point>) -> Option::<SearchBox> {
+    let first = points.next()?;
+    return Some(points.fold(SearchBox::from_point(&first), |current, point| SearchBox {
+        lat_from: f64::min(current.lat_from, point.lat),
+        lat_to: f64::max(current.lat_to, point.lat),
+        lon_from: f64::min(current.lon_from, point.lon),
+        lon_to: f64::max(current.lon_to, point.lon)
+    }));
+}
+
+// one or both SearchBoxes have at least one common point with the box
+fn intersects_any(targets: &(Option::<SearchBox>, Option::<SearchBox>), search_box: &SearchBox) -> bool {
+    return targets.0.is_some_and(|target| target.intersects(search_box)) || targets.1.is_some_and(|target| target.intersects(search_box));
+}
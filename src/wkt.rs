@@ -0,0 +1,357 @@
+use std::fmt;
+
+use crate::{geo_error::GeoError, geo_point::Point, polygon::Polygon};
+
+/*
+Geometry of a WKT literal https://en.wikipedia.org/wiki/Well-known_text_representation_of_geometry
+Coordinates in WKT are in degrees and in the order longitude latitude (like "Point(11.5754 48.1371)" in Wikidata),
+the crate's points are in radians. Vertices get their index in their list as id
+*/
+#[derive(Debug, Clone)]
+pub enum Geometry {
+    Point(Point),
+    LineString(Vec<Point>),
+    Polygon(Polygon),
+    MultiPolygon(Vec<Polygon>)
+}
+
+impl Geometry {
+
+    // parse POINT, LINESTRING, POLYGON or MULTIPOLYGON. Keywords are case-insensitive, rings of polygons must be closed
+    pub fn from_wkt(text: &str) -> Result<Self, GeoError> {
+        let mut parser = Parser { text, position: 0 };
+        let geometry = parser.geometry()?;
+        parser.skip_whitespace();
+        if parser.position < text.len() {
+            return Err(parser.error("unexpected text after the geometry"));
+        }
+        return Ok(geometry);
+    }
+
+    pub fn to_wkt(&self) -> String {
+        return self.to_string();
+    }
+}
+
+impl fmt::Display for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Geometry::Point(point) => write!(f, "POINT({})", Coordinates(std::slice::from_ref(point), false)),
+            Geometry::LineString(points) if points.is_empty() => write!(f, "LINESTRING EMPTY"),
+            Geometry::LineString(points) => write!(f, "LINESTRING({})", Coordinates(points, false)),
+            Geometry::Polygon(polygon) => write!(f, "POLYGON{}", Rings(polygon)),
+            Geometry::MultiPolygon(polygons) if polygons.is_empty() => write!(f, "MULTIPOLYGON EMPTY"),
+            Geometry::MultiPolygon(polygons) => {
+                write!(f, "MULTIPOLYGON(")?;
+                for (idx, polygon) in polygons.iter().enumerate() {
+                    write!(f, "{}{}", if idx == 0 { "" } else { ", " }, Rings(polygon))?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+// coordinates are written with 9 decimals (about 0.1 mm) => conversion from radians does not leave digits like 3.0000000000000004
+const DEGREES_SCALE: f64 = 1e9;
+
+fn round_degrees(value: f64) -> f64 {
+    return (value * DEGREES_SCALE).round() / DEGREES_SCALE;
+}
+
+// "lon lat, lon lat, ..." in degrees. true => the first point is repeated at the end to close a ring
+struct Coordinates<'a>(&'a [Point], bool);
+
+impl fmt::Display for Coordinates<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Coordinates(points, closed) = *self;
+        let closing = if closed { points.first() } else { None };
+        for (idx, point) in points.iter().chain(closing).enumerate() {
+            write!(f, "{}{} {}", if idx == 0 { "" } else { ", " }, round_degrees(point.lon_degrees()), round_degrees(point.lat_degrees()))?;
+        }
+        return Ok(());
+    }
+}
+
+// "((exterior), (hole), ...)" of a polygon
+struct Rings<'a>(&'a Polygon);
+
+impl fmt::Display for Rings<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(({})", Coordinates(self.0.exterior(), true))?;
+        for hole in self.0.holes() {
+            write!(f, ", ({})", Coordinates(hole, true))?;
+        }
+        return write!(f, ")");
+    }
+}
+
+// recursive descent over the text. position = byte offset of the next character, is reported in errors
+struct Parser<'a> {
+    text: &'a str,
+    position: usize
+}
+
+impl Parser<'_> {
+
+    fn geometry(&mut self) -> Result<Geometry, GeoError> {
+
+        let (start, keyword) = self.keyword()?;
+
+        match keyword.as_str() {
+            "POINT" => {
+                if self.empty()? {
+                    return Err(GeoError::InvalidWkt { position: start, message: "POINT EMPTY has no coordinates".to_string() });
+                }
+                self.expect('(')?;
+                let point = self.coordinate(0)?;
+                self.expect(')')?;
+                return Ok(Geometry::Point(point));
+            },
+            "LINESTRING" => {
+                if self.empty()? {
+                    return Ok(Geometry::LineString(Vec::new()));
+                }
+                return Ok(Geometry::LineString(self.coordinates()?.1));
+            },
+            "POLYGON" => {
+                if self.empty()? {
+                    return Err(GeoError::InvalidWkt { position: start, message: "POLYGON EMPTY has no exterior ring".to_string() });
+                }
+                return Ok(Geometry::Polygon(self.polygon()?));
+            },
+            "MULTIPOLYGON" => {
+                if self.empty()? {
+                    return Ok(Geometry::MultiPolygon(Vec::new()));
+                }
+                self.expect('(')?;
+                let mut polygons = vec![self.polygon()?];
+                while self.next_in_list()? {
+                    polygons.push(self.polygon()?);
+                }
+                return Ok(Geometry::MultiPolygon(polygons));
+            },
+            _ => {
+                return Err(GeoError::InvalidWkt { position: start, message: format!("unsupported geometry type {:?}, expected POINT, LINESTRING, POLYGON or MULTIPOLYGON", keyword) });
+            }
+        }
+    }
+
+    // "((exterior), (hole), ...)"
+    fn polygon(&mut self) -> Result<Polygon, GeoError> {
+
+        self.skip_whitespace();
+        let start = self.position;
+
+        self.expect('(')?;
+        let exterior = self.ring()?;
+        let mut holes = Vec::new();
+        while self.next_in_list()? {
+            holes.push(self.ring()?);
+        }
+
+        // errors of rings (too few vertices, ...) point to the polygon
+        return Polygon::new(exterior, holes).map_err(|error| GeoError::InvalidWkt { position: start, message: error.to_string() });
+    }
+
+    // "(lon lat, ...)" where the last vertex repeats the first one
+    fn ring(&mut self) -> Result<Vec<Point>, GeoError> {
+
+        let (start, vertices) = self.coordinates()?;
+
+        let (first, last) = (&vertices[0], &vertices[vertices.len() - 1]);
+        if vertices.len() < 4 || first.lat != last.lat || first.lon != last.lon {
+            return Err(GeoError::InvalidWkt { position: start, message: "ring must be closed and have at least 4 vertices (the last one equal to the first)".to_string() });
+        }
+
+        return Ok(vertices);
+    }
+
+    // "(lon lat, ...)" with at least one coordinate. Returns the position of the list
+    fn coordinates(&mut self) -> Result<(usize, Vec<Point>), GeoError> {
+
+        self.skip_whitespace();
+        let start = self.position;
+
+        self.expect('(')?;
+        let mut points = vec![self.coordinate(0)?];
+        while self.next_in_list()? {
+            points.push(self.coordinate(points.len())?);
+        }
+
+        return Ok((start, points));
+    }
+
+    // "lon lat" in degrees
+    fn coordinate(&mut self, id: usize) -> Result<Point, GeoError> {
+
+        let (lon_position, lon) = self.number()?;
+        let (lat_position, lat) = self.number()?;
+
+        if !(-180.0..=180.0).contains(&lon) {
+            return Err(GeoError::InvalidWkt { position: lon_position, message: format!("longitude {} is out of range [-180, 180]", lon) });
+        }
+        if !(-90.0..=90.0).contains(&lat) {
+            return Err(GeoError::InvalidWkt { position: lat_position, message: format!("latitude {} is out of range [-90, 90]", lat) });
+        }
+
+        return Ok(Point::from_degrees(id, lat, lon));
+    }
+
+    // after an element of a list: true => ',' and another element follows, false => ')' closed the list
+    fn next_in_list(&mut self) -> Result<bool, GeoError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(',') => {
+                self.position += 1;
+                return Ok(true);
+            },
+            Some(')') => {
+                self.position += 1;
+                return Ok(false);
+            },
+            _ => return Err(self.error("expected ',' or ')'"))
+        }
+    }
+
+    // keyword EMPTY instead of coordinates
+    fn empty(&mut self) -> Result<bool, GeoError> {
+        self.skip_whitespace();
+        if self.peek().is_some_and(|character| character.is_ascii_alphabetic()) {
+            let (start, keyword) = self.keyword()?;
+            if keyword != "EMPTY" {
+                return Err(GeoError::InvalidWkt { position: start, message: format!("expected '(' or EMPTY, found {:?}", keyword) });
+            }
+            return Ok(true);
+        }
+        return Ok(false);
+    }
+
+    // word of letters in upper case together with its position
+    fn keyword(&mut self) -> Result<(usize, String), GeoError> {
+        self.skip_whitespace();
+        let start = self.position;
+        let length = self.rest().find(|character: char| !character.is_ascii_alphabetic()).unwrap_or(self.rest().len());
+        if length == 0 {
+            return Err(self.error("expected a geometry type"));
+        }
+        self.position += length;
+        return Ok((start, self.text[start..self.position].to_ascii_uppercase()));
+    }
+
+    fn number(&mut self) -> Result<(usize, f64), GeoError> {
+        self.skip_whitespace();
+        let start = self.position;
+        let length = self.rest().find(|character: char| !(character.is_ascii_digit() || "+-.eE".contains(character))).unwrap_or(self.rest().len());
+        if length == 0 {
+            return Err(self.error("expected a number"));
+        }
+        let token = &self.text[start..start + length];
+        let value = token.parse::<f64>().map_err(|_| GeoError::InvalidWkt { position: start, message: format!("invalid number {:?}", token) })?;
+        self.position += length;
+        return Ok((start, value));
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), GeoError> {
+        self.skip_whitespace();
+        if self.peek() != Some(expected) {
+            return Err(self.error(&format!("expected {:?}", expected)));
+        }
+        self.position += 1;
+        return Ok(());
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.position += self.rest().len() - self.rest().trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        return self.rest().chars().next();
+    }
+
+    fn rest(&self) -> &str {
+        return &self.text[self.position..];
+    }
+
+    // error at the current position, tells what was found there
+    fn error(&self, message: &str) -> GeoError {
+        let found = match self.peek() {
+            Some(character) => format!("{:?}", character),
+            None => "end of input".to_string()
+        };
+        return GeoError::InvalidWkt { position: self.position, message: format!("{}, found {}", message, found) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{geo_error::GeoError, geo_point::Point, polygon::Polygon};
+    use super::Geometry;
+
+    // text => geometry => text gives the canonical form, which is parsed back to the same geometry
+    fn assert_round_trip(text: &str, expected: &str) {
+        let written = Geometry::from_wkt(text).unwrap().to_wkt();
+        assert_eq!(written, expected);
+        assert_eq!(Geometry::from_wkt(&written).unwrap().to_wkt(), expected);
+    }
+
+    fn error_position(text: &str) -> usize {
+        match Geometry::from_wkt(text) {
+            Err(GeoError::InvalidWkt { position, .. }) => return position,
+            other => panic!("{:?} is parsed as {:?}", text, other)
+        }
+    }
+
+    #[test]
+    fn point_of_readme_round_trip() {
+        let point = Point::from_wkt(5, "Point(11.5754 48.1371)").unwrap();
+        assert_eq!(point.id, 5);
+        assert!(f64::abs(point.lon_degrees() - 11.5754) < 1e-12);
+        assert!(f64::abs(point.lat_degrees() - 48.1371) < 1e-12);
+        assert_eq!(point.to_wkt(), "POINT(11.5754 48.1371)");
+
+        assert_round_trip("  point ( 11.5754   48.1371 ) ", "POINT(11.5754 48.1371)");
+        assert_round_trip("LINESTRING(179.5 10, -179.5 10.25)", "LINESTRING(179.5 10, -179.5 10.25)");
+    }
+
+    #[test]
+    fn polygons_round_trip() {
+        let with_hole = "POLYGON((0 0, 10 0, 10 10, 0 10, 0 0), (2 2, 2 4, 4 4, 4 2, 2 2))";
+        assert_round_trip(with_hole, with_hole);
+
+        let polygon = Polygon::from_wkt(with_hole).unwrap();
+        assert_eq!(polygon.holes().count(), 1);
+        assert_eq!(polygon.to_wkt(), with_hole);
+
+        let multi = "MULTIPOLYGON(((0 0, 1 0, 1 1, 0 0)), ((170 -5, -170 -5, -170 5, 170 5, 170 -5), (175 -1, 175 1, 179 1, 179 -1, 175 -1)))";
+        assert_round_trip(multi, multi);
+        match Geometry::from_wkt(multi).unwrap() {
+            Geometry::MultiPolygon(polygons) => assert_eq!(polygons.iter().map(|polygon| polygon.holes().count()).collect::<Vec<usize>>(), vec![0, 1]),
+            other => panic!("{:?} is not a MULTIPOLYGON", other)
+        }
+
+        assert_round_trip("multipolygon empty", "MULTIPOLYGON EMPTY");
+    }
+
+    #[test]
+    fn errors_point_to_their_position() {
+        assert_eq!(
+            Geometry::from_wkt("POINT(1 2").map(|_| ()),
+            Err(GeoError::InvalidWkt { position: 9, message: "expected ')', found end of input".to_string() })
+        );
+
+        // the position of the number that is out of range
+        assert_eq!(error_position("POINT(200 1)"), 6);
+        assert_eq!(error_position("POINT(1 -91)"), 8);
+
+        // rings that are not closed point to their start
+        assert_eq!(error_position("POLYGON((0 0, 10 0, 10 10, 0 10))"), 8);
+        let text = "MULTIPOLYGON(((0 0, 1 0, 1 1, 0 0)), ((5 5, 6 5, 6 6, 5 6)))";
+        assert_eq!(error_position(text), text.find("((5").unwrap() + 1);
+        let text = "POLYGON((0 0, 10 0, 10 10, 0 10, 0 0), (2 2, 2 4, 4 4, 2 2.5))";
+        assert_eq!(error_position(text), text.find("(2").unwrap());
+
+        assert_eq!(error_position("POINT(1 2) x"), 11);
+        assert_eq!(error_position("CIRCLE(1 2)"), 0);
+    }
+}